@@ -1,5 +1,7 @@
 use winit::event::MouseButton;
 
+use crate::pipeline_state::PipelineState;
+use crate::wgsl_struct::WgslStruct;
 use crate::winit_input_helper::WinitInputHelper;
 // use winit_input_helper::WinitInputHelper;
 
@@ -8,7 +10,7 @@ use crate::winit_input_helper::WinitInputHelper;
 // To avoid having to do linear algebra ourselves, most computations are done in the shader.
 // This is sub-optimal. Improving this is left as an exercise to the reader.
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, WgslStruct)]
 pub struct CameraLookAt {
     /// Object the camera is looking at.
     pub center: [f32; 4],
@@ -20,6 +22,14 @@ pub struct CameraLookAt {
     pub distance: f32,
 }
 
+// Updated by the runner, see `layout_fingerprint` in `lib.rs`.
+crate::impl_type_layout!(CameraLookAt {
+    center,
+    longitude,
+    latitude,
+    distance,
+});
+
 impl Default for CameraLookAt {
     fn default() -> Self {
         // See object in 0,0,0 from the front top left
@@ -33,6 +43,22 @@ impl Default for CameraLookAt {
 }
 
 impl CameraLookAt {
+    /// Save the camera under `prefix`, see `PipelineFuncs::export_state`.
+    pub fn export_state(&self, state: &mut PipelineState, prefix: &str) {
+        state.set(&format!("{prefix}center"), self.center);
+        state.set(&format!("{prefix}longitude"), self.longitude);
+        state.set(&format!("{prefix}latitude"), self.latitude);
+        state.set(&format!("{prefix}distance"), self.distance);
+    }
+
+    /// Restore the camera saved by [`Self::export_state`].
+    pub fn import_state(&mut self, state: &PipelineState, prefix: &str) {
+        state.restore(&format!("{prefix}center"), &mut self.center);
+        state.restore(&format!("{prefix}longitude"), &mut self.longitude);
+        state.restore(&format!("{prefix}latitude"), &mut self.latitude);
+        state.restore(&format!("{prefix}distance"), &mut self.distance);
+    }
+
     /// Pan the camera with middle mouse click, zoom with scroll wheel, orbit with right mouse click.
     pub fn update(&mut self, input: &WinitInputHelper, window_size: [f32; 2]) -> bool {
         let mut captured = false;
@@ -0,0 +1,277 @@
+//! Shader flattened by the preprocessor, along with a source map
+//! pointing each of its lines back to the file it comes from.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::pipeline::{DiagnosticLabel, Severity, ShaderDiagnostic, ShaderLocation};
+
+/// Original location of a line of a composed shader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLine {
+    /// Shader file, relative to the shader folder.
+    pub file: String,
+    /// 1-based line number in `file`.
+    pub line: usize,
+}
+
+/// Extensions of the files shaders are built from:
+/// the shaders of every [`ShaderLanguage`] and the `.glsl` files they include.
+pub const SHADER_EXTENSIONS: &[&str] = &["wgsl", "vert", "frag", "comp", "spv", "glsl"];
+
+/// Language of a shader file, picked from its extension.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    /// `.wgsl` files, and files with an unknown extension.
+    #[default]
+    Wgsl,
+    /// `.vert`, `.frag` and `.comp` files, one stage per file.
+    Glsl(naga::ShaderStage),
+    /// Precompiled `.spv` files, which are not preprocessed.
+    SpirV,
+}
+
+impl ShaderLanguage {
+    /// Language of a shader file extension, `None` if it is not a shader.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "wgsl" => Some(Self::Wgsl),
+            "vert" => Some(Self::Glsl(naga::ShaderStage::Vertex)),
+            "frag" => Some(Self::Glsl(naga::ShaderStage::Fragment)),
+            "comp" => Some(Self::Glsl(naga::ShaderStage::Compute)),
+            "spv" => Some(Self::SpirV),
+            _ => None,
+        }
+    }
+
+    /// Language of a shader file, wgsl if the extension is unknown.
+    pub fn from_file(name: &str) -> Self {
+        name.rsplit_once('.')
+            .and_then(|(_, extension)| Self::from_extension(extension))
+            .unwrap_or_default()
+    }
+}
+
+/// Shader built from a file and all its imports.
+#[derive(Clone, Debug, Default)]
+pub struct ComposedShader {
+    /// Language of the root file, shared by its imports.
+    pub language: ShaderLanguage,
+    /// Flattened wgsl or glsl code.
+    pub code: String,
+    /// Original location of each line of `code`.
+    /// `None` for lines generated by the preprocessor, such as injected defines.
+    pub lines: Vec<Option<SourceLine>>,
+    /// Files included in the shader, each with the files it imports directly.
+    pub imports: BTreeMap<String, Vec<String>>,
+    /// Content of a SPIR-V file, empty for other languages.
+    pub binary: Vec<u8>,
+    /// Capabilities naga validates the shader with.
+    /// Defaults to naga's, supported by any device running the demos,
+    /// see [`device_capabilities`] to allow the features of a device.
+    pub capabilities: naga::valid::Capabilities,
+}
+
+/// Shader capabilities enabled by the features of a device, mirroring the validation of wgpu
+/// so shaders using features the device lacks, such as `f64` or push constants, fail offline too.
+/// The device does not expose its downlevel flags, naga's defaults are kept for them.
+pub fn device_capabilities(features: wgpu::Features) -> naga::valid::Capabilities {
+    use naga::valid::Capabilities as Caps;
+    use wgpu::Features;
+
+    let mut capabilities = Caps::default();
+    for (capability, feature) in [
+        (Caps::PUSH_CONSTANT, Features::PUSH_CONSTANTS),
+        (Caps::FLOAT64, Features::SHADER_F64),
+        (Caps::PRIMITIVE_INDEX, Features::SHADER_PRIMITIVE_INDEX),
+        (
+            Caps::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING
+                | Caps::SAMPLER_NON_UNIFORM_INDEXING,
+            Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
+        ),
+        (
+            Caps::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
+            Features::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
+        ),
+        (
+            Caps::STORAGE_TEXTURE_16BIT_NORM_FORMATS,
+            Features::TEXTURE_FORMAT_16BIT_NORM,
+        ),
+        (Caps::MULTIVIEW, Features::MULTIVIEW),
+        (Caps::EARLY_DEPTH_TEST, Features::SHADER_EARLY_DEPTH_TEST),
+        (Caps::SHADER_INT64, Features::SHADER_INT64),
+        (
+            Caps::SHADER_INT64_ATOMIC_MIN_MAX,
+            Features::SHADER_INT64_ATOMIC_MIN_MAX | Features::SHADER_INT64_ATOMIC_ALL_OPS,
+        ),
+        (
+            Caps::SHADER_INT64_ATOMIC_ALL_OPS,
+            Features::SHADER_INT64_ATOMIC_ALL_OPS,
+        ),
+        (Caps::DUAL_SOURCE_BLENDING, Features::DUAL_SOURCE_BLENDING),
+        (
+            Caps::SUBGROUP,
+            Features::SUBGROUP | Features::SUBGROUP_VERTEX,
+        ),
+        (Caps::SUBGROUP_BARRIER, Features::SUBGROUP_BARRIER),
+        (Caps::SUBGROUP_VERTEX_STAGE, Features::SUBGROUP_VERTEX),
+    ] {
+        capabilities.set(capability, features.intersects(feature));
+    }
+    capabilities
+}
+
+impl ComposedShader {
+    /// Wrap a precompiled SPIR-V file, which has no imports.
+    pub fn from_spirv(name: &str, binary: Vec<u8>) -> Self {
+        Self {
+            language: ShaderLanguage::SpirV,
+            imports: BTreeMap::from([(name.to_owned(), vec![])]),
+            binary,
+            ..Default::default()
+        }
+    }
+
+    /// Source to create a wgpu shader module from.
+    /// SPIR-V must be validated first: wgpu panics on malformed binaries.
+    pub fn source(&self) -> wgpu::ShaderSource<'_> {
+        match self.language {
+            ShaderLanguage::Wgsl => wgpu::ShaderSource::Wgsl(Cow::Borrowed(&self.code)),
+            ShaderLanguage::Glsl(stage) => wgpu::ShaderSource::Glsl {
+                shader: Cow::Borrowed(&self.code),
+                stage,
+                // Defines are already written in the code by the preprocessor.
+                defines: Default::default(),
+            },
+            ShaderLanguage::SpirV => {
+                wgpu::ShaderSource::SpirV(wgpu::util::make_spirv_raw(&self.binary))
+            }
+        }
+    }
+
+    /// Append a line to the shader, remembering where it comes from.
+    pub fn push_line(&mut self, line: &str, origin: Option<SourceLine>) {
+        self.code.push_str(line);
+        self.code.push('\n');
+        self.lines.push(origin);
+    }
+
+    /// Convert a 1-based line and column of the composed shader
+    /// to a location in the original files.
+    pub fn locate(&self, line: usize, column: usize, length: usize) -> ShaderLocation {
+        match self.lines.get(line.wrapping_sub(1)) {
+            Some(Some(origin)) => ShaderLocation {
+                file: origin.file.clone(),
+                line: origin.line,
+                column,
+                length,
+            },
+            _ => ShaderLocation {
+                file: "<generated>".to_owned(),
+                line,
+                column,
+                length,
+            },
+        }
+    }
+
+    /// Parse and validate the shader with naga to report its first error
+    /// with locations in the original files instead of the composed shader.
+    /// Returns `None` if naga does not find any error.
+    pub fn diagnose(&self) -> Option<ShaderDiagnostic> {
+        self.validate().err()
+    }
+
+    /// Parse and validate the shader with naga, without requiring a gpu.
+    /// Errors are reported with locations in the original files.
+    pub fn validate(&self) -> Result<naga::Module, ShaderDiagnostic> {
+        Ok(self.validate_with_info()?.0)
+    }
+
+    /// Like `validate`, also returning the information naga gathered on the module,
+    /// such as the resources used by each entry point.
+    pub fn validate_with_info(
+        &self,
+    ) -> Result<(naga::Module, naga::valid::ModuleInfo), ShaderDiagnostic> {
+        let module = match self.language {
+            ShaderLanguage::Wgsl => naga::front::wgsl::parse_str(&self.code).map_err(|error| {
+                let labels: Vec<_> = error
+                    .labels()
+                    .map(|(span, label)| (span, label.to_owned()))
+                    .collect();
+                self.to_diagnostic(error.message(), vec![], &labels)
+            })?,
+            ShaderLanguage::Glsl(stage) => naga::front::glsl::Frontend::default()
+                .parse(&stage.into(), &self.code)
+                .map_err(|errors| {
+                    // The glsl front-end reports every error it found, label each of them.
+                    let labels: Vec<_> = errors
+                        .errors
+                        .iter()
+                        .map(|error| (error.meta, error.kind.to_string()))
+                        .collect();
+                    let message = labels
+                        .first()
+                        .map_or_else(String::new, |(_, message)| message.clone());
+                    self.to_diagnostic(&message, vec![], &labels)
+                })?,
+            // SPIR-V has no source to point to.
+            ShaderLanguage::SpirV => {
+                naga::front::spv::parse_u8_slice(&self.binary, &Default::default())
+                    .map_err(|error| self.to_diagnostic(&error.to_string(), vec![], &[]))?
+            }
+        };
+
+        let mut validator =
+            naga::valid::Validator::new(naga::valid::ValidationFlags::all(), self.capabilities);
+        let info = validator.validate(&module).map_err(|error| {
+            // naga nests its validation errors (function > expression > ...),
+            // only the full chain gives enough context.
+            let mut chain = vec![];
+            let mut source = std::error::Error::source(error.as_inner());
+            while let Some(inner) = source {
+                chain.push(inner.to_string());
+                source = inner.source();
+            }
+
+            let labels: Vec<_> = error.spans().cloned().collect();
+            self.to_diagnostic(&error.as_inner().to_string(), chain, &labels)
+        })?;
+
+        Ok((module, info))
+    }
+
+    /// Convert naga spans to locations in the original files.
+    fn to_diagnostic(
+        &self,
+        message: &str,
+        chain: Vec<String>,
+        labels: &[(naga::Span, String)],
+    ) -> ShaderDiagnostic {
+        let labels = labels
+            .iter()
+            .filter(|(span, _)| span.is_defined())
+            .map(|(span, label)| {
+                let location = span.location(&self.code);
+                let line = location.line_number as usize;
+                let snippet = self.code.lines().nth(line - 1).unwrap_or_default();
+                DiagnosticLabel {
+                    location: self.locate(
+                        line,
+                        location.line_position as usize,
+                        location.length as usize,
+                    ),
+                    message: label.clone(),
+                    snippet: snippet.trim_end().to_owned(),
+                }
+            })
+            .collect();
+
+        ShaderDiagnostic {
+            severity: Severity::Error,
+            message: message.to_owned(),
+            labels,
+            chain,
+        }
+    }
+}
@@ -17,6 +17,21 @@ pub struct CurrentInput {
     pub text: Vec<Key>,
 }
 
+crate::impl_type_layout!(CurrentInput {
+    mouse_actions,
+    key_actions,
+    scancode_actions,
+    key_held,
+    scancode_held,
+    mouse_held,
+    cursor_point,
+    cursor_point_prev,
+    mouse_diff,
+    y_scroll_diff,
+    x_scroll_diff,
+    text,
+});
+
 impl CurrentInput {
     pub fn new() -> CurrentInput {
         CurrentInput {
@@ -140,6 +155,12 @@ pub enum KeyAction {
     Released(Key),
 }
 
+crate::impl_type_layout!(enum KeyAction {
+    Pressed(key: Key),
+    PressedOs(key: Key),
+    Released(key: Key),
+});
+
 #[derive(Clone, PartialEq)]
 pub enum ScanCodeAction {
     Pressed(PhysicalKey),
@@ -147,12 +168,23 @@ pub enum ScanCodeAction {
     Released(PhysicalKey),
 }
 
+crate::impl_type_layout!(enum ScanCodeAction {
+    Pressed(key: PhysicalKey),
+    PressedOs(key: PhysicalKey),
+    Released(key: PhysicalKey),
+});
+
 #[derive(Clone)]
 pub enum MouseAction {
     Pressed(MouseButton),
     Released(MouseButton),
 }
 
+crate::impl_type_layout!(enum MouseAction {
+    Pressed(button: MouseButton),
+    Released(button: MouseButton),
+});
+
 pub fn mouse_button_to_int(button: &MouseButton) -> usize {
     match button {
         MouseButton::Left => 0,
@@ -4,33 +4,59 @@
 // This example cannot run in WebGL because it uses compute shaders.
 // See the README for more details.
 
+use std::collections::HashMap;
+
 use nanorand::{Rng, WyRand};
 use wgpu::util::DeviceExt;
 
 use crate::frame_rate::FrameRate;
-use crate::pipeline::{PipelineError, PipelineFuncs};
-use crate::ShaderBuilderForLibrary;
-
+use crate::pipeline::{PassRebuild, PipelineError, PipelineFuncs, ShaderChanges};
+use crate::pipeline_constants::PipelineConstants;
+use crate::pipeline_state::PipelineState;
+use crate::struct_layout::LayoutRules;
+use crate::wgsl_struct::WgslStruct;
+use crate::{ShaderBuilderForLibrary, ShaderDefines};
+
+// Both constants are injected in the compute shader, see `Pipeline::compute_shader_defines`.
 const NUM_PARTICLES: u32 = 1500;
 const PARTICLES_PER_GROUP: u32 = 64;
 
+const COMPUTE_SHADER: &str = "demos/boids/compute.wgsl";
+const DRAW_SHADER: &str = "demos/boids/draw.wgsl";
+
 struct ComputePass {
     compute_pipeline: wgpu::ComputePipeline,
     particle_bind_groups: Vec<wgpu::BindGroup>,
     work_group_count: u32,
     parameters: wgpu::Buffer,
-    bind_group_layout: wgpu::BindGroupLayout,
+    /// `override` constants of the compute shader, edited in the ui.
+    constants: PipelineConstants,
 }
 
+crate::impl_type_layout!(ComputePass {
+    compute_pipeline,
+    particle_bind_groups,
+    work_group_count,
+    parameters,
+    constants,
+});
+
 struct RenderPass {
     render_pipeline: wgpu::RenderPipeline,
     particle_buffers: Vec<wgpu::Buffer>,
     vertices_buffer: wgpu::Buffer,
 }
 
+crate::impl_type_layout!(RenderPass {
+    render_pipeline,
+    particle_buffers,
+    vertices_buffer,
+});
+
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct BoidsSettings {
+// Imported in `boids/compute.wgsl` with `#import "rust:BoidsSettings"`.
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, WgslStruct)]
+pub(crate) struct BoidsSettings {
     delta_t: f32,        // cohesion
     rule1_distance: f32, // separation
     rule2_distance: f32, // alignment
@@ -41,6 +67,17 @@ struct BoidsSettings {
     speed: f32,
 }
 
+crate::impl_type_layout!(BoidsSettings {
+    delta_t,
+    rule1_distance,
+    rule2_distance,
+    rule3_distance,
+    rule1_scale,
+    rule2_scale,
+    rule3_scale,
+    speed,
+});
+
 impl BoidsSettings {
     pub fn new() -> Self {
         Self {
@@ -67,8 +104,19 @@ pub struct Pipeline {
     render_pass: RenderPass,
     frame_rate: FrameRate,
     last_update: web_time::Instant,
+    /// An override constant was edited, the compute pass needs to be recreated.
+    constants_changed: bool,
 }
 
+crate::impl_type_layout!(Pipeline {
+    settings,
+    compute_pass,
+    render_pass,
+    frame_rate,
+    last_update,
+    constants_changed,
+});
+
 impl PipelineFuncs for Pipeline {
     fn required_downlevel_capabilities() -> wgpu::DownlevelCapabilities {
         wgpu::DownlevelCapabilities {
@@ -104,19 +152,32 @@ impl PipelineFuncs for Pipeline {
             render_pass,
             frame_rate: FrameRate::new(100),
             last_update: web_time::Instant::now(),
+            constants_changed: false,
         })
     }
 
-    /// update is called for any WindowEvent not handled by the framework
+    /// Recreate the pipelines whose shaders changed.
     fn update_passes(
         &mut self,
         surface: &wgpu::Surface,
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
+        changes: &ShaderChanges,
     ) -> Result<(), PipelineError> {
-        self.compute_pass.compute_pipeline =
-            Self::create_compute_pipeline(device, &self.compute_pass.bind_group_layout)?;
-        self.render_pass.render_pipeline = Self::create_render_pipeline(surface, device, adapter)?;
+        // Both passes are replaced only if both succeed,
+        // a broken draw shader must not pair the new compute pass with the old render pass.
+        let mut rebuild = PassRebuild::new(changes);
+        // The bind groups are recreated too since their layouts come from the shader,
+        // the constants edited in the ui are kept.
+        let constants = self.compute_pass.constants.values().clone();
+        let particle_buffers = &self.render_pass.particle_buffers;
+        rebuild.rebuild(&mut self.compute_pass, COMPUTE_SHADER, || {
+            Self::create_compute_pass(device, particle_buffers, constants)
+        })?;
+        rebuild.rebuild(&mut self.render_pass.render_pipeline, DRAW_SHADER, || {
+            Self::create_render_pipeline(surface, device, adapter)
+        })?;
+        rebuild.commit();
 
         Ok(())
     }
@@ -169,6 +230,11 @@ impl PipelineFuncs for Pipeline {
 
         ui.separator();
 
+        ui.label("Override constants");
+        self.constants_changed |= self.compute_pass.constants.draw_ui(ui);
+
+        ui.separator();
+
         ui.label(std::format!(
             "speed: {} (rust only for demo purposes)",
             self.settings.speed
@@ -176,6 +242,41 @@ impl PipelineFuncs for Pipeline {
         ui.label(std::format!("framerate: {:.0}fps", self.frame_rate.get()));
     }
 
+    /// Recreate the compute pass when an override constant changed.
+    fn take_pass_updates(&mut self) -> Option<ShaderChanges> {
+        std::mem::take(&mut self.constants_changed)
+            .then(|| ShaderChanges::Files(vec![COMPUTE_SHADER.to_owned()]))
+    }
+
+    /// Keep the ui settings and the constants across library reloads.
+    /// The particles are reset.
+    fn export_state(&self, state: &mut PipelineState) {
+        let settings = &self.settings;
+        state.set("rule1_distance", settings.rule1_distance);
+        state.set("rule1_scale", settings.rule1_scale);
+        state.set("rule2_distance", settings.rule2_distance);
+        state.set("rule2_scale", settings.rule2_scale);
+        state.set("rule3_distance", settings.rule3_distance);
+        state.set("rule3_scale", settings.rule3_scale);
+        self.compute_pass
+            .constants
+            .export_state(state, "constants.");
+    }
+
+    fn import_state(&mut self, state: &PipelineState) {
+        let settings = &mut self.settings;
+        state.restore("rule1_distance", &mut settings.rule1_distance);
+        state.restore("rule1_scale", &mut settings.rule1_scale);
+        state.restore("rule2_distance", &mut settings.rule2_distance);
+        state.restore("rule2_scale", &mut settings.rule2_scale);
+        state.restore("rule3_distance", &mut settings.rule3_distance);
+        state.restore("rule3_scale", &mut settings.rule3_scale);
+        self.constants_changed |= self
+            .compute_pass
+            .constants
+            .import_state(state, "constants.");
+    }
+
     /// render is called each frame, dispatching compute groups proportional
     ///   a TriangleList draw call for all NUM_PARTICLES at 3 vertices each
     fn render(&self, view: &wgpu::TextureView, device: &wgpu::Device, queue: &wgpu::Queue) {
@@ -240,30 +341,90 @@ impl PipelineFuncs for Pipeline {
 }
 
 impl Pipeline {
-    fn create_compute_pipeline(
-        device: &wgpu::Device,
-        compute_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Result<wgpu::ComputePipeline, PipelineError> {
-        let compute_shader =
-            ShaderBuilderForLibrary::create_module(device, "demos/boids/compute.wgsl")?;
-
-        let compute_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("compute"),
-                bind_group_layouts: &[compute_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+    /// Constants shared with the compute shader.
+    pub(crate) fn compute_shader_defines() -> ShaderDefines {
+        ShaderDefines::from([
+            ("NUM_PARTICLES".to_owned(), NUM_PARTICLES.into()),
+            ("PARTICLES_PER_GROUP".to_owned(), PARTICLES_PER_GROUP.into()),
+        ])
+    }
 
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Compute pipeline"),
-            layout: Some(&compute_pipeline_layout),
-            module: &compute_shader,
-            entry_point: "main",
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
-            cache: None,
+    /// Create the compute pipeline, its parameter buffer and its two bind groups,
+    /// one for each particle buffer as the src where the alternate buffer is used as the dst.
+    /// The `override` constants of the shader are set to `constants`.
+    fn create_compute_pass(
+        device: &wgpu::Device,
+        particle_buffers: &[wgpu::Buffer],
+        constants: HashMap<String, f64>,
+    ) -> Result<ComputePass, PipelineError> {
+        // Bind group layouts are reflected from the shader.
+        let (compute_shader, bindings) = ShaderBuilderForLibrary::create_module_with_bindings(
+            device,
+            COMPUTE_SHADER,
+            &Self::compute_shader_defines(),
+        )?;
+        // The wgsl struct is generated from rust, this checks the uniform buffer rules.
+        bindings.check_struct_layout::<BoidsSettings>("BoidsSettings", LayoutRules::Uniform)?;
+        let constants = bindings.pipeline_constants(constants)?;
+        let bind_group_layouts = bindings.create_bind_group_layouts(device);
+
+        // Written each frame in `update`.
+        let parameters = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Simulation Parameter Buffer"),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            size: BoidsSettings::get_size(),
+            mapped_at_creation: false,
         });
 
-        Ok(compute_pipeline)
+        let particle_bind_groups = (0..2)
+            .map(|i| {
+                bindings.create_bind_group(
+                    device,
+                    &bind_group_layouts,
+                    0,
+                    &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: parameters.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: particle_buffers[i].as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: particle_buffers[(i + 1) % 2].as_entire_binding(), // bind to opposite buffer
+                        },
+                    ],
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        let compute_pipeline_layout = bindings.create_pipeline_layout(device, &bind_group_layouts);
+
+        let compute_pipeline = constants.create_compute_pipeline(
+            device,
+            &wgpu::ComputePipelineDescriptor {
+                label: Some("Compute pipeline"),
+                layout: Some(&compute_pipeline_layout),
+                module: &compute_shader,
+                entry_point: "main",
+                compilation_options: Default::default(),
+                cache: None,
+            },
+        );
+
+        // calculates number of work groups from PARTICLES_PER_GROUP constant
+        let work_group_count =
+            ((NUM_PARTICLES as f32) / (PARTICLES_PER_GROUP as f32)).ceil() as u32;
+
+        Ok(ComputePass {
+            compute_pipeline,
+            particle_bind_groups,
+            work_group_count,
+            parameters,
+            constants,
+        })
     }
 
     fn create_render_pipeline(
@@ -271,17 +432,18 @@ impl Pipeline {
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
     ) -> Result<wgpu::RenderPipeline, PipelineError> {
-        let draw_shader = ShaderBuilderForLibrary::create_module(device, "demos/boids/draw.wgsl")?;
+        let (draw_shader, bindings) = ShaderBuilderForLibrary::create_module_with_bindings(
+            device,
+            DRAW_SHADER,
+            &ShaderDefines::new(),
+        )?;
 
         let swapchain_capabilities = surface.get_capabilities(adapter);
         let swapchain_format = swapchain_capabilities.formats[0];
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("render"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[],
-            });
+        // Particles are read from vertex buffers, the shader should not bind anything.
+        let bind_group_layouts = bindings.create_bind_group_layouts(device);
+        let render_pipeline_layout = bindings.create_pipeline_layout(device, &bind_group_layouts);
 
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: None,
@@ -324,13 +486,6 @@ impl Pipeline {
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
     ) -> Result<(ComputePass, RenderPass), PipelineError> {
-        let sim_param_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Simulation Parameter Buffer"),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            size: BoidsSettings::get_size(),
-            mapped_at_creation: false,
-        });
-
         let vertex_buffer_data = [-0.01f32, -0.02, 0.01, -0.02, 0.00, 0.02];
         let vertices_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
@@ -353,44 +508,6 @@ impl Pipeline {
 
         let mut particle_buffers = Vec::<wgpu::Buffer>::new();
 
-        let compute_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(BoidsSettings::get_size()),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new((NUM_PARTICLES * 16) as _),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new((NUM_PARTICLES * 16) as _),
-                        },
-                        count: None,
-                    },
-                ],
-                label: None,
-            });
-
-        let mut particle_bind_groups = Vec::<wgpu::BindGroup>::new();
         for i in 0..2 {
             particle_buffers.push(
                 device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -403,66 +520,11 @@ impl Pipeline {
             );
         }
 
-        // create two bind groups, one for each buffer as the src
-        // where the alternate buffer is used as the dst
-
-        for i in 0..2 {
-            particle_bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &compute_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: sim_param_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: particle_buffers[i].as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: particle_buffers[(i + 1) % 2].as_entire_binding(), // bind to opposite buffer
-                    },
-                ],
-                label: None,
-            }));
-        }
-
-        for i in 0..2 {
-            particle_bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &compute_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: sim_param_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: particle_buffers[i].as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: particle_buffers[(i + 1) % 2].as_entire_binding(), // bind to opposite buffer
-                    },
-                ],
-                label: None,
-            }));
-        }
-
-        // calculates number of work groups from PARTICLES_PER_GROUP constant
-        let work_group_count =
-            ((NUM_PARTICLES as f32) / (PARTICLES_PER_GROUP as f32)).ceil() as u32;
-
-        let compute_pipeline = Self::create_compute_pipeline(device, &compute_bind_group_layout)?;
+        let compute_pass = Self::create_compute_pass(device, &particle_buffers, HashMap::new())?;
         let render_pipeline = Self::create_render_pipeline(surface, device, adapter)?;
 
         Ok((
-            ComputePass {
-                compute_pipeline,
-                particle_bind_groups,
-                work_group_count,
-                parameters: sim_param_buffer,
-                bind_group_layout: compute_bind_group_layout,
-            },
+            compute_pass,
             RenderPass {
                 render_pipeline,
                 particle_buffers,
@@ -1,6 +1,15 @@
+use std::collections::HashMap;
+
 use crate::frame_rate::FrameRate;
-use crate::pipeline::{PipelineError, PipelineFuncs};
-use crate::ShaderBuilderForLibrary;
+use crate::pipeline::{PassRebuild, PipelineError, PipelineFuncs, ShaderChanges};
+use crate::pipeline_constants::PipelineConstants;
+use crate::pipeline_state::PipelineState;
+use crate::struct_layout::LayoutRules;
+use crate::wgsl_struct::WgslStruct;
+use crate::{ShaderBuilderForLibrary, ShaderDefines};
+
+/// Shader of the render pass.
+const SHADER: &str = "demos/polygon/draw.wgsl";
 
 /// A simple struct to store a wgpu pass with a uniform buffer.
 #[derive(Debug)]
@@ -11,13 +20,23 @@ pub struct Pass {
     pub bind_group: wgpu::BindGroup,
     /// Single uniform buffer for this pass.
     pub uniform_buf: wgpu::Buffer,
+    /// `override` constants of the shader, edited in the ui.
+    pub constants: PipelineConstants,
 }
 
+crate::impl_type_layout!(Pass {
+    pipeline,
+    bind_group,
+    uniform_buf,
+    constants,
+});
+
 /// Settings for the `PipelineFuncs`
 /// `polygon_edge_count` is not exposed in ui on purpose for  purposes
 /// change it in the code with hot-reload enable to see it working.
+/// Imported in `polygon/draw.wgsl` with `#import "rust:PolygonSettings"`.
 #[repr(C)]
-#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable, WgslStruct)]
 pub struct PolygonSettings {
     // elapsed take the speed into consideration
     elapsed: f32,
@@ -29,6 +48,13 @@ pub struct PolygonSettings {
     speed: f32, // exposed in ui
 }
 
+crate::impl_type_layout!(PolygonSettings {
+    elapsed,
+    polygon_size,
+    polygon_edge_count,
+    speed,
+});
+
 impl PolygonSettings {
     pub fn new() -> Self {
         Self {
@@ -43,11 +69,12 @@ impl PolygonSettings {
         std::mem::size_of::<Self>() as _
     }
 }
+
 ///  Pipeline showcasing the three type of live updates via the rotation of a regular polygon
 ///
 ///     shader: `draw.wgsl`
 ///     rust: `polygon_edge_count` in [`PipelineFuncs::update`]
-///     ui: `size` and `speed`
+///     ui: `size` and `speed`, and the `override` constants of the shader
 #[derive(Debug)]
 pub struct Pipeline {
     render_pass: Pass,
@@ -55,8 +82,19 @@ pub struct Pipeline {
     last_update: web_time::Instant,
     settings: PolygonSettings,
     frame_rate: FrameRate,
+    /// An override constant was edited, the render pass needs to be recreated.
+    constants_changed: bool,
 }
 
+crate::impl_type_layout!(Pipeline {
+    render_pass,
+    _start_time,
+    last_update,
+    settings,
+    frame_rate,
+    constants_changed,
+});
+
 impl PipelineFuncs for Pipeline {
     /// Create pipeline.
     /// Assume the `render_pipeline` will be properly initialized.
@@ -66,7 +104,7 @@ impl PipelineFuncs for Pipeline {
         adapter: &wgpu::Adapter,
         _surface_configuration: &wgpu::SurfaceConfiguration,
     ) -> Result<Self, PipelineError> {
-        let render_pass = Self::create_render_pass(surface, device, adapter)?;
+        let render_pass = Self::create_render_pass(surface, device, adapter, HashMap::new())?;
 
         Ok(Self {
             render_pass,
@@ -74,6 +112,7 @@ impl PipelineFuncs for Pipeline {
             last_update: web_time::Instant::now(),
             settings: PolygonSettings::new(),
             frame_rate: FrameRate::default(),
+            constants_changed: false,
         })
     }
 
@@ -88,8 +127,15 @@ impl PipelineFuncs for Pipeline {
         surface: &wgpu::Surface,
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
+        changes: &ShaderChanges,
     ) -> Result<(), PipelineError> {
-        self.render_pass = Self::create_render_pass(surface, device, adapter)?;
+        // Keep the constants edited in the ui.
+        let constants = self.render_pass.constants.values().clone();
+        let mut rebuild = PassRebuild::new(changes);
+        rebuild.rebuild(&mut self.render_pass, SHADER, || {
+            Self::create_render_pass(surface, device, adapter, constants)
+        })?;
+        rebuild.commit();
         Ok(())
     }
 
@@ -162,12 +208,36 @@ impl PipelineFuncs for Pipeline {
         ui.add(egui::Slider::new(&mut self.settings.polygon_size, 0.0..=1.0).text("size"));
         ui.add(egui::Slider::new(&mut self.settings.speed, 0.0..=20.0).text("speed"));
         ui.separator();
+        ui.label("Override constants");
+        self.constants_changed |= self.render_pass.constants.draw_ui(ui);
+        ui.separator();
         ui.label(std::format!(
             "edge count: {} (rust only for demo purposes)",
             self.settings.polygon_edge_count
         ));
         ui.label(std::format!("framerate: {:.0}fps", self.frame_rate.get()));
     }
+
+    /// Recreate the render pass when an override constant changed.
+    fn take_pass_updates(&mut self) -> Option<ShaderChanges> {
+        std::mem::take(&mut self.constants_changed)
+            .then(|| ShaderChanges::Files(vec![SHADER.to_owned()]))
+    }
+
+    /// Keep the ui settings and the constants across library reloads.
+    fn export_state(&self, state: &mut PipelineState) {
+        state.set("elapsed", self.settings.elapsed);
+        state.set("polygon_size", self.settings.polygon_size);
+        state.set("speed", self.settings.speed);
+        self.render_pass.constants.export_state(state, "constants.");
+    }
+
+    fn import_state(&mut self, state: &PipelineState) {
+        state.restore("elapsed", &mut self.settings.elapsed);
+        state.restore("polygon_size", &mut self.settings.polygon_size);
+        state.restore("speed", &mut self.settings.speed);
+        self.constants_changed |= self.render_pass.constants.import_state(state, "constants.");
+    }
 }
 
 impl Pipeline {
@@ -178,52 +248,62 @@ impl Pipeline {
         surface: &wgpu::Surface,
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
-        uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        layout: &wgpu::PipelineLayout,
+        constants: &PipelineConstants,
     ) -> Result<wgpu::RenderPipeline, PipelineError> {
-        let shader = ShaderBuilderForLibrary::create_module(device, "demos/polygon/draw.wgsl")?;
-        // let shader = ShaderBuilder::create_module(device, "test_preprocessor/draw.wgsl")?; // uncomment to test preprocessor
-
         let swapchain_capabilities = surface.get_capabilities(adapter);
         let swapchain_format = swapchain_capabilities.formats[0];
 
-        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[uniforms_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+        // The constants are set in both stages by the helper.
+        let pipeline = constants.create_render_pipeline(
+            device,
+            &wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(swapchain_format.into())],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
             },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(swapchain_format.into())],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+        );
 
         Ok(pipeline)
     }
 
-    /// Create render pass.
+    /// Create render pass, setting the `override` constants of the shader to `constants`.
     /// Will return an error in debug, and crash in release/wasm if a shader is malformed.
     fn create_render_pass(
         surface: &wgpu::Surface,
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
+        constants: HashMap<String, f64>,
     ) -> Result<Pass, PipelineError> {
+        // Bind group layouts are reflected from the shader.
+        let (shader, bindings) = ShaderBuilderForLibrary::create_module_with_bindings(
+            device,
+            SHADER,
+            &ShaderDefines::new(),
+        )?;
+        // let shader = ShaderBuilder::create_module(device, "test_preprocessor/draw.wgsl")?; // uncomment to test preprocessor
+        // The wgsl struct is generated from rust, this checks the uniform buffer rules.
+        bindings.check_struct_layout::<PolygonSettings>("PolygonSettings", LayoutRules::Uniform)?;
+        let constants = bindings.pipeline_constants(constants)?;
+        let bind_group_layouts = bindings.create_bind_group_layouts(device);
+
         // create uniform buffer.
         let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Uniforms Buffer"),
@@ -232,37 +312,26 @@ impl Pipeline {
             mapped_at_creation: false,
         });
 
-        let uniforms_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("uniforms_bind_group_layout"),
-            });
-
-        let uniforms_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniforms_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
+        // Fails if the shader does not bind exactly these resources.
+        let uniforms_bind_group = bindings.create_bind_group(
+            device,
+            &bind_group_layouts,
+            0,
+            &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: uniforms.as_entire_binding(),
             }],
-            label: Some("uniforms_bind_group"),
-        });
+        )?;
 
+        let layout = bindings.create_pipeline_layout(device, &bind_group_layouts);
         let pipeline =
-            Self::create_render_pipeline(surface, device, adapter, &uniforms_bind_group_layout)?;
+            Self::create_render_pipeline(surface, device, adapter, &shader, &layout, &constants)?;
 
         Ok(Pass {
             pipeline,
             bind_group: uniforms_bind_group,
             uniform_buf: uniforms,
+            constants,
         })
     }
 }
@@ -2,8 +2,17 @@ use wgpu::util::DeviceExt;
 
 use crate::camera_control::CameraLookAt;
 use crate::frame_rate::FrameRate;
-use crate::pipeline::{PipelineError, PipelineFuncs};
-use crate::ShaderBuilderForLibrary;
+use crate::pipeline::{PassRebuild, PipelineError, PipelineFuncs, ShaderChanges};
+use crate::pipeline_state::PipelineState;
+use crate::struct_layout::LayoutRules;
+use crate::{ShaderBuilderForLibrary, ShaderDefines};
+
+/// Render the 2d scene instead of the 3d one.
+/// Change it with hot-reload enabled to switch between both shader variants.
+const RAYMARCHING_2D: bool = false;
+
+/// Shader of the render pass.
+const SHADER: &str = "demos/raymarching/draw.wgsl";
 
 /// A simple struct to store a wgpu pass with a uniform buffer.
 #[derive(Debug)]
@@ -22,6 +31,15 @@ pub struct Pass {
     pub index_count: u32,
 }
 
+crate::impl_type_layout!(Pass {
+    pipeline,
+    bind_group,
+    uniform_buf,
+    index_buffer,
+    vertex_buffer,
+    index_count,
+});
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct Vertex {
@@ -52,6 +70,20 @@ pub struct RaymarchingSettings {
     _padding: [f32; 2], // padding for alignment
 }
 
+// Checked against `Uniforms` in `raymarching/common.wgsl` when creating the pipeline.
+crate::impl_struct_layout!(RaymarchingSettings {
+    camera,
+    size,
+    elapsed,
+    _padding
+});
+crate::impl_type_layout!(RaymarchingSettings {
+    camera,
+    size,
+    elapsed,
+    _padding
+});
+
 ///  raymarching pipeline.
 /// Everything is done in the shader.
 /// Provides both 2d and 3d raymarching.
@@ -64,6 +96,14 @@ pub struct Pipeline {
     settings: RaymarchingSettings,
 }
 
+crate::impl_type_layout!(Pipeline {
+    render_pass,
+    _start_time,
+    last_update,
+    frame_rate,
+    settings,
+});
+
 impl RaymarchingSettings {
     pub fn new(surface_configuration: &wgpu::SurfaceConfiguration) -> Self {
         Self {
@@ -113,8 +153,13 @@ impl PipelineFuncs for Pipeline {
         surface: &wgpu::Surface,
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
+        changes: &ShaderChanges,
     ) -> Result<(), PipelineError> {
-        self.render_pass = Self::create_render_pass(surface, device, adapter)?;
+        let mut rebuild = PassRebuild::new(changes);
+        rebuild.rebuild(&mut self.render_pass, SHADER, || {
+            Self::create_render_pass(surface, device, adapter)
+        })?;
+        rebuild.commit();
         Ok(())
     }
 
@@ -191,9 +236,25 @@ impl PipelineFuncs for Pipeline {
     fn get_camera(&mut self) -> Option<&mut crate::camera_control::CameraLookAt> {
         Some(&mut self.settings.camera)
     }
+
+    /// Keep the camera across library reloads.
+    fn export_state(&self, state: &mut PipelineState) {
+        state.set("elapsed", self.settings.elapsed);
+        self.settings.camera.export_state(state, "camera.");
+    }
+
+    fn import_state(&mut self, state: &PipelineState) {
+        state.restore("elapsed", &mut self.settings.elapsed);
+        self.settings.camera.import_state(state, "camera.");
+    }
 }
 
 impl Pipeline {
+    /// Defines selecting the 2d or 3d variant of the shader.
+    pub(crate) fn shader_defines(raymarching_2d: bool) -> ShaderDefines {
+        ShaderDefines::from([("RAYMARCHING_2D".to_owned(), raymarching_2d.into())])
+    }
+
     /// Create render pipeline.
     /// In debug mode it will return a `PipelineError` if it failed compiling a shader
     /// In release/wasm, il will crash since wgpu does not return errors in such situations.
@@ -201,31 +262,23 @@ impl Pipeline {
         surface: &wgpu::Surface,
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
-        uniforms_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        layout: &wgpu::PipelineLayout,
     ) -> Result<wgpu::RenderPipeline, PipelineError> {
-        let shader = ShaderBuilderForLibrary::create_module(device, "demos/raymarching/draw.wgsl")?;
-        // let shader = ShaderBuilder::create_module(device, "test_preprocessor/draw.wgsl")?; // uncomment to test preprocessor
-
         let swapchain_capabilities = surface.get_capabilities(adapter);
         let swapchain_format = swapchain_capabilities.formats[0];
 
-        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[uniforms_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Raymarching Render Pipeline"),
-            layout: Some(&layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: "vs_main",
                 buffers: &[Vertex::desc()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
+                module: shader,
                 entry_point: "fs_main",
                 targets: &[Some(swapchain_format.into())],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
@@ -247,6 +300,16 @@ impl Pipeline {
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
     ) -> Result<Pass, PipelineError> {
+        // Bind group layouts are reflected from the shader.
+        let (shader, bindings) = ShaderBuilderForLibrary::create_module_with_bindings(
+            device,
+            SHADER,
+            &Self::shader_defines(RAYMARCHING_2D),
+        )?;
+        // let shader = ShaderBuilder::create_module(device, "test_preprocessor/draw.wgsl")?; // uncomment to test preprocessor
+        bindings.check_struct_layout::<RaymarchingSettings>("Uniforms", LayoutRules::Uniform)?;
+        let bind_group_layouts = bindings.create_bind_group_layouts(device);
+
         // create uniform buffer.
         let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Camera Buffer"),
@@ -255,29 +318,16 @@ impl Pipeline {
             mapped_at_creation: false,
         });
 
-        let uniforms_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("uniforms_bind_group_layout"),
-            });
-
-        let uniforms_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniforms_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
+        // Fails if the shader does not bind exactly these resources.
+        let uniforms_bind_group = bindings.create_bind_group(
+            device,
+            &bind_group_layouts,
+            0,
+            &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: uniforms.as_entire_binding(),
             }],
-            label: Some("uniforms_bind_group"),
-        });
+        )?;
 
         // lib.rs
         const VERTICES: &[Vertex] = &[
@@ -310,8 +360,8 @@ impl Pipeline {
             usage: wgpu::BufferUsages::INDEX,
         });
 
-        let pipeline =
-            Self::create_render_pipeline(surface, device, adapter, &uniforms_bind_group_layout)?;
+        let layout = bindings.create_pipeline_layout(device, &bind_group_layouts);
+        let pipeline = Self::create_render_pipeline(surface, device, adapter, &shader, &layout)?;
 
         Ok(Pass {
             pipeline,
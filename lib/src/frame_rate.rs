@@ -10,6 +10,11 @@ pub struct FrameRate {
     current_index: usize,
 }
 
+crate::impl_type_layout!(FrameRate {
+    window,
+    current_index
+});
+
 impl FrameRate {
     /// Create a new slicing window with the given size.
     pub fn new(window_size: usize) -> Self {
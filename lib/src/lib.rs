@@ -7,14 +7,23 @@ mod current_input;
 pub mod winit_input_helper;
 
 pub mod camera_control;
+pub mod composed_shader;
 pub mod demo_pipelines;
 mod frame_rate;
 pub mod mouse_input;
 pub mod pipeline;
+pub mod pipeline_constants;
+pub mod pipeline_registry;
+pub mod pipeline_state;
 pub mod reload_flags;
-mod shader_builder;
+pub mod shader_bindings;
+pub mod shader_builder;
+pub mod struct_layout;
+pub mod type_layout;
+pub mod wgsl_struct;
 
-use crate::pipeline::{PipelineError, PipelineFuncs};
+use crate::pipeline::{PipelineError, ShaderChanges};
+use crate::type_layout::TypeLayout;
 
 /// default shader builder for this library's shaders.
 pub type ShaderBuilderForLibrary = ShaderBuilderFor<LibraryShaders>;
@@ -22,33 +31,58 @@ pub type ShaderBuilderForLibrary = ShaderBuilderFor<LibraryShaders>;
 // Any type from libthat is used in the functions signatures in lib.rs should be re-exported here
 // and re-imported in hot_lib.rs.
 pub use crate::camera_control::CameraLookAt;
-/// Specify which pipeline we want to run here.
-pub use demo_pipelines::polygon::Pipeline as CurrentPipeline;
+/// Register new pipelines in [`pipeline_registry`].
+pub use pipeline_registry::{CurrentPipeline, PipelineHandle, PipelineInfo};
 use shader_builder::{LibraryShaders, ShaderBuilderFor};
-// pub use demo_pipelines::boids::Pipeline as CurrentPipeline;
-// pub use demo_pipelines::raymarching::Pipeline as CurrentPipeline;
+pub use shader_builder::{ShaderDefValue, ShaderDefines};
+pub use type_layout::LayoutFingerprint;
+
+/// Shader folders imported with a prefix, see [`shader_builder::ShaderRoot`].
+pub const SHADER_ROOTS: &[shader_builder::ShaderRoot] = &[shader_builder::ShaderRoot::of::<
+    shader_builder::SharedShaders,
+>(
+    "shared:",
+    concat!(env!("CARGO_MANIFEST_DIR"), "/../shared_shaders"),
+)];
 
 /// Hot-reloading does not support generics, so we need to specialize
 /// the functions we want to call from the outside.
 ///
+/// `name` is one of the names of `get_pipeline_registry`.
+///
 /// # Errors
 /// - `PipelineError::ShaderParseError` when the shader could not be compiled.
+/// - `PipelineError::UnknownPipeline` when no pipeline is named `name`.
 #[no_mangle]
 pub fn create_pipeline(
+    name: &str,
     surface: &wgpu::Surface,
     device: &wgpu::Device,
     adapter: &wgpu::Adapter,
     surface_configuration: &wgpu::SurfaceConfiguration,
-) -> Result<CurrentPipeline, PipelineError> {
-    CurrentPipeline::init(surface, device, adapter, surface_configuration)
+) -> Result<PipelineHandle, PipelineError> {
+    CurrentPipeline::init(name, surface, device, adapter, surface_configuration)
+        .map(PipelineHandle::new)
+}
+
+/// Free a pipeline created by `create_pipeline`, with the drop glue of this library.
+#[no_mangle]
+pub fn drop_pipeline(pipeline: PipelineHandle) {
+    drop(pipeline.into_box());
 }
 
 /// Contrary to `PipelineFuncs::get_name`, this function returns a String
 /// and not a &'static str since we cannot return a static reference
 /// from a dynamic library.
 #[no_mangle]
-pub fn get_pipeline_name() -> String {
-    CurrentPipeline::get_name().to_owned()
+pub fn get_pipeline_name(pipeline: &PipelineHandle) -> String {
+    pipeline.get().name().to_owned()
+}
+
+/// Every pipeline the runner can switch to, with the features and limits it requires.
+#[no_mangle]
+pub fn get_pipeline_registry() -> Vec<PipelineInfo> {
+    pipeline_registry::pipelines()
 }
 
 /// Resize pipeline. This is called when the main window was resized,
@@ -56,78 +90,117 @@ pub fn get_pipeline_name() -> String {
 /// depending on the window size.
 #[no_mangle]
 pub fn resize_pipeline(
-    pipeline: &mut CurrentPipeline,
+    pipeline: &mut PipelineHandle,
     surface_configuration: &wgpu::SurfaceConfiguration,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) {
-    pipeline.resize(surface_configuration, device, queue);
+    pipeline
+        .get_mut()
+        .funcs_mut()
+        .resize(surface_configuration, device, queue);
 }
 
 /// Update pipeline passes. Called when a shader needs to be reloaded
 /// or the libray is done reloading,
+/// `changes` allows to only rebuild the passes using the modified shaders.
 ///
 /// # Errors
 /// - `PipelineError::ShaderParseError` when the shader could not be compiled.
 #[no_mangle]
 pub fn update_pipeline_passes(
-    pipeline: &mut CurrentPipeline,
+    pipeline: &mut PipelineHandle,
     surface: &wgpu::Surface,
     device: &wgpu::Device,
     adapter: &wgpu::Adapter,
+    changes: &ShaderChanges,
 ) -> Result<(), PipelineError> {
-    pipeline.update_passes(surface, device, adapter)
+    pipeline
+        .get_mut()
+        .funcs_mut()
+        .update_passes(surface, device, adapter, changes)
 }
 
-/// Update pipeline. Called each frame before rendering.
+/// Passes the pipeline asked to rebuild, see `PipelineFuncs::take_pass_updates`.
 #[no_mangle]
-pub fn update_pipeline(pipeline: &mut CurrentPipeline, queue: &wgpu::Queue) {
-    pipeline.update(queue);
+pub fn take_pipeline_pass_updates(pipeline: &mut PipelineHandle) -> Option<ShaderChanges> {
+    pipeline.get_mut().funcs_mut().take_pass_updates()
 }
 
-/// Render frame.
+/// Files included by the shaders built since the last call,
+/// the runner only clears the errors of the shaders a successful update rebuilt.
 #[no_mangle]
-pub fn render_frame(
-    pipeline: &CurrentPipeline,
-    view: &wgpu::TextureView,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-) {
-    pipeline.render(view, device, queue);
+pub fn take_built_shader_files() -> Vec<String> {
+    shader_builder::take_built_files().into_iter().collect()
 }
 
-/// Render ui. Called after `render_frame` to ensure ui is on top.
+/// Save the pipeline state before the library is unloaded, see `PipelineFuncs::export_state`.
+/// The state is serialized since its types may change with the reload.
 #[no_mangle]
-pub fn render_ui(pipeline: &mut CurrentPipeline, ui: &mut egui::Ui) {
-    pipeline.draw_ui(ui);
+pub fn export_pipeline_state(pipeline: &PipelineHandle) -> String {
+    let mut state = pipeline_state::PipelineState::new();
+    pipeline.get().funcs().export_state(&mut state);
+    state.to_blob()
 }
 
+/// Restore the state saved by `export_pipeline_state` in a newly created pipeline.
 #[no_mangle]
-pub fn process_input(pipeline: &mut CurrentPipeline, input: &winit_input_helper::WinitInputHelper) {
-    pipeline.process_input(input);
+pub fn import_pipeline_state(pipeline: &mut PipelineHandle, state: &str) {
+    pipeline
+        .get_mut()
+        .funcs_mut()
+        .import_state(&pipeline_state::PipelineState::from_blob(state));
 }
 
+/// Update pipeline. Called each frame before rendering.
 #[no_mangle]
-pub fn pipeline_optional_features() -> wgpu::Features {
-    CurrentPipeline::optional_features()
+pub fn update_pipeline(pipeline: &mut PipelineHandle, queue: &wgpu::Queue) {
+    pipeline.get_mut().funcs_mut().update(queue);
 }
 
+/// Render frame.
+#[no_mangle]
+pub fn render_frame(
+    pipeline: &PipelineHandle,
+    view: &wgpu::TextureView,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) {
+    pipeline.get().funcs().render(view, device, queue);
+}
+
+/// Render ui. Called after `render_frame` to ensure ui is on top.
 #[no_mangle]
-pub fn pipeline_required_features() -> wgpu::Features {
-    CurrentPipeline::required_features()
+pub fn render_ui(pipeline: &mut PipelineHandle, ui: &mut egui::Ui) {
+    pipeline.get_mut().funcs_mut().draw_ui(ui);
 }
 
 #[no_mangle]
-pub fn pipeline_required_downlevel_capabilities() -> wgpu::DownlevelCapabilities {
-    CurrentPipeline::required_downlevel_capabilities()
+pub fn process_input(pipeline: &mut PipelineHandle, input: &winit_input_helper::WinitInputHelper) {
+    pipeline.get_mut().funcs_mut().process_input(input);
 }
 
+/// Fingerprint of the layout of `CurrentPipeline` and of the types the runner shares with the library.
+/// Compared by the runner before and after each reload, since values created by the old library
+/// cannot be used by the new one if their layout changed.
 #[no_mangle]
-pub fn pipeline_required_limits() -> wgpu::Limits {
-    CurrentPipeline::required_limits()
+pub fn layout_fingerprint() -> LayoutFingerprint {
+    LayoutFingerprint {
+        pipeline: type_layout::fingerprint(&[CurrentPipeline::describe()]),
+        // Every type the runner passes to or gets from the library functions, and uses with its own code.
+        boundary: type_layout::fingerprint(&[
+            CameraLookAt::describe(),
+            winit_input_helper::WinitInputHelper::describe(),
+            PipelineError::describe(),
+            ShaderChanges::describe(),
+            PipelineInfo::describe(),
+            pipeline_state::PipelineState::describe(),
+            LayoutFingerprint::describe(),
+        ]),
+    }
 }
 
 #[no_mangle]
-pub fn get_pipeline_camera(pipeline: &mut CurrentPipeline) -> Option<&mut CameraLookAt> {
-    pipeline.get_camera()
+pub fn get_pipeline_camera(pipeline: &mut PipelineHandle) -> Option<&mut CameraLookAt> {
+    pipeline.get_mut().funcs_mut().get_camera()
 }
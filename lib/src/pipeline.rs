@@ -1,25 +1,215 @@
 use std::fmt;
 
+use crate::pipeline_state::PipelineState;
 use crate::winit_input_helper;
 
 /// Errors a pipeline can return
+#[derive(Clone)]
 pub enum PipelineError {
+    /// Shader could not be parsed or validated by naga.
     /// This encapsulate naga::front::wgsl::ParseError that is not available in wasm it seems.
     /// The output is the same minus the colors.
-    ShaderParseError(String),
-    ShaderNotFound(String),
+    ShaderParseError(ShaderDiagnostic),
+    /// Shader file could not be loaded.
+    ShaderNotFound { file: String },
+    /// Shader file is not valid utf8.
+    InvalidUtf8 { file: String },
+    /// Invalid preprocessor directive, such as an unbalanced `#endif`.
+    ShaderPreprocessError {
+        location: ShaderLocation,
+        message: String,
+        /// Line of code the error points to, for display purposes.
+        snippet: String,
+    },
+    /// Malformed `#import` line.
+    InvalidImport {
+        location: ShaderLocation,
+        line: String,
+    },
+    /// Files importing each other, the first file being repeated at the end of the chain.
+    ImportCycle { chain: Vec<String> },
+    /// Error raised by the device that naga did not catch, such as unsupported features.
+    DeviceValidation { label: String, message: String },
+    /// Resources bound from rust do not match the bindings declared by the shader.
+    BindingMismatch {
+        shader: String,
+        mismatches: Vec<String>,
+    },
+    /// Rust struct sent to the gpu does not have the layout of the wgsl struct reading it.
+    StructLayoutMismatch {
+        shader: String,
+        rust_struct: String,
+        wgsl_struct: String,
+        mismatches: Vec<String>,
+    },
+    /// Values given to the `override` constants of a shader do not match their declarations.
+    PipelineConstantMismatch {
+        shader: String,
+        mismatches: Vec<String>,
+    },
+    /// No pipeline of the registry has this name, for instance after it was renamed by a reload.
+    UnknownPipeline { name: String },
+    /// Device lacks features, capabilities or limits the pipeline requires.
+    UnsupportedPipeline { name: String, reason: String },
+    /// Value of a shader define cannot be written in the shader, such as `NaN`.
+    InvalidDefine { name: String, message: String },
+}
+
+// Returned to the runner, see `layout_fingerprint` in `lib.rs`.
+crate::impl_type_layout!(enum PipelineError {
+    ShaderParseError(diagnostic: ShaderDiagnostic),
+    ShaderNotFound { file: String },
+    InvalidUtf8 { file: String },
+    ShaderPreprocessError {
+        location: ShaderLocation,
+        message: String,
+        snippet: String,
+    },
+    InvalidImport {
+        location: ShaderLocation,
+        line: String,
+    },
+    ImportCycle { chain: Vec<String> },
+    DeviceValidation { label: String, message: String },
+    BindingMismatch {
+        shader: String,
+        mismatches: Vec<String>,
+    },
+    StructLayoutMismatch {
+        shader: String,
+        rust_struct: String,
+        wgsl_struct: String,
+        mismatches: Vec<String>,
+    },
+    PipelineConstantMismatch {
+        shader: String,
+        mismatches: Vec<String>,
+    },
+    UnknownPipeline { name: String },
+    UnsupportedPipeline { name: String, reason: String },
+    InvalidDefine { name: String, message: String },
+});
+
+impl PipelineError {
+    /// Location of the error in a shader file, if known.
+    pub fn location(&self) -> Option<&ShaderLocation> {
+        match self {
+            Self::ShaderParseError(diagnostic) => diagnostic.location(),
+            Self::ShaderPreprocessError { location, .. } | Self::InvalidImport { location, .. } => {
+                Some(location)
+            }
+            Self::ShaderNotFound { .. }
+            | Self::InvalidUtf8 { .. }
+            | Self::ImportCycle { .. }
+            | Self::DeviceValidation { .. }
+            | Self::BindingMismatch { .. }
+            | Self::StructLayoutMismatch { .. }
+            | Self::PipelineConstantMismatch { .. }
+            | Self::UnknownPipeline { .. }
+            | Self::UnsupportedPipeline { .. }
+            | Self::InvalidDefine { .. } => None,
+        }
+    }
+
+    /// Line of code [`Self::location`] points to, if known.
+    pub fn snippet(&self) -> Option<&str> {
+        match self {
+            Self::ShaderParseError(diagnostic) => diagnostic
+                .labels
+                .first()
+                .map(|label| label.snippet.as_str()),
+            Self::ShaderPreprocessError { snippet, .. } => Some(snippet),
+            Self::InvalidImport { line, .. } => Some(line),
+            _ => None,
+        }
+    }
+
+    /// Shader file the error comes from, if known.
+    pub fn file(&self) -> Option<&str> {
+        if let Some(location) = self.location() {
+            return Some(&location.file);
+        }
+        match self {
+            Self::ShaderNotFound { file } | Self::InvalidUtf8 { file } => Some(file),
+            Self::ImportCycle { chain } => chain.first().map(String::as_str),
+            Self::DeviceValidation { label, .. } => Some(label),
+            Self::BindingMismatch { shader, .. }
+            | Self::StructLayoutMismatch { shader, .. }
+            | Self::PipelineConstantMismatch { shader, .. } => Some(shader),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for PipelineError {
     /// Display error.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::ShaderParseError(message) => {
+            Self::ShaderParseError(diagnostic) => {
                 writeln!(f, "Shader parse Error:")?;
+                writeln!(f, "{diagnostic}")?;
+            }
+            Self::ShaderNotFound { file } => {
+                writeln!(f, "Shader not found: Could not load shader file: {file}")?;
+            }
+            Self::InvalidUtf8 { file } => {
+                writeln!(f, "Shader file {file} is not a valid utf8.")?;
+            }
+            Self::ShaderPreprocessError {
+                location, message, ..
+            } => {
+                writeln!(f, "Shader preprocess error: {location}: {message}")?;
+            }
+            Self::InvalidImport { location, line } => {
+                writeln!(
+                    f,
+                    "Invalid import syntax: {location}: expected #import \"file\", got `{line}`"
+                )?;
+            }
+            Self::ImportCycle { chain } => {
+                writeln!(f, "Import cycle: {}", chain.join(" -> "))?;
+            }
+            Self::DeviceValidation { label, message } => {
+                writeln!(f, "Device validation error in {label}:")?;
                 writeln!(f, "{message}")?;
             }
-            Self::ShaderNotFound(message) => {
-                writeln!(f, "Shader not found: {message}")?;
+            Self::BindingMismatch { shader, mismatches } => {
+                writeln!(f, "Binding mismatch in {shader}:")?;
+                for mismatch in mismatches {
+                    writeln!(f, "  - {mismatch}")?;
+                }
+            }
+            Self::StructLayoutMismatch {
+                shader,
+                rust_struct,
+                wgsl_struct,
+                mismatches,
+            } => {
+                writeln!(
+                    f,
+                    "Layout mismatch between rust {rust_struct} and wgsl {wgsl_struct} in {shader}:"
+                )?;
+                for mismatch in mismatches {
+                    writeln!(f, "  - {mismatch}")?;
+                }
+            }
+            Self::PipelineConstantMismatch { shader, mismatches } => {
+                writeln!(f, "Override constant mismatch in {shader}:")?;
+                for mismatch in mismatches {
+                    writeln!(f, "  - {mismatch}")?;
+                }
+            }
+            Self::UnknownPipeline { name } => {
+                writeln!(f, "Unknown pipeline: no pipeline is named {name}")?;
+            }
+            Self::UnsupportedPipeline { name, reason } => {
+                writeln!(f, "Pipeline {name} cannot run on this device:")?;
+                for reason in reason.lines() {
+                    writeln!(f, "  - {reason}")?;
+                }
+            }
+            Self::InvalidDefine { name, message } => {
+                writeln!(f, "Invalid shader define {name}: {message}")?;
             }
         }
         Ok(())
@@ -33,10 +223,213 @@ impl fmt::Debug for PipelineError {
     }
 }
 
+/// Location in a shader file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderLocation {
+    /// Shader file, relative to the shader folder.
+    pub file: String,
+    /// 1-based line.
+    pub line: usize,
+    /// 1-based column, in bytes.
+    pub column: usize,
+    /// Length of the span, in bytes.
+    pub length: usize,
+}
+
+crate::impl_type_layout!(ShaderLocation {
+    file,
+    line,
+    column,
+    length
+});
+
+impl ShaderLocation {
+    /// Carets below the span of the location in `snippet`, the line it points to.
+    /// Locations count bytes, the marker counts chars so it stays aligned on non-ascii lines.
+    pub fn marker(&self, snippet: &str) -> String {
+        let start = self.column.saturating_sub(1);
+        let end = start + self.length;
+        let padding = snippet
+            .char_indices()
+            .take_while(|(index, _)| *index < start)
+            .count();
+        let width = snippet
+            .char_indices()
+            .filter(|(index, _)| (start..end).contains(index))
+            .count();
+        format!("{}{}", " ".repeat(padding), "^".repeat(width.max(1)))
+    }
+}
+
+impl fmt::Display for ShaderLocation {
+    /// Display location as `file:line:column`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Severity of a shader diagnostic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+crate::impl_type_layout!(
+    enum Severity {
+        Error,
+        Warning,
+    }
+);
+
+/// Label attached to a diagnostic, pointing to the code it refers to.
+#[derive(Clone, Debug)]
+pub struct DiagnosticLabel {
+    pub location: ShaderLocation,
+    pub message: String,
+    /// Line of code the label points to, for display purposes.
+    pub snippet: String,
+}
+
+crate::impl_type_layout!(DiagnosticLabel {
+    location,
+    message,
+    snippet
+});
+
+/// Diagnostic reported by naga when compiling a shader.
+#[derive(Clone, Debug)]
+pub struct ShaderDiagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Labels in naga order, the first one being the primary location.
+    pub labels: Vec<DiagnosticLabel>,
+    /// Nested errors causing this one, from outermost to innermost.
+    pub chain: Vec<String>,
+}
+
+crate::impl_type_layout!(ShaderDiagnostic {
+    severity,
+    message,
+    labels,
+    chain
+});
+
+impl ShaderDiagnostic {
+    /// Primary location of the diagnostic, if naga provided one.
+    pub fn location(&self) -> Option<&ShaderLocation> {
+        self.labels.first().map(|label| &label.location)
+    }
+}
+
+impl fmt::Display for ShaderDiagnostic {
+    /// Display diagnostic as `file:line:column: message`,
+    /// followed by each label and the line it points to.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = self.location() {
+            write!(f, "{location}: ")?;
+        }
+        write!(f, "{}", self.message)?;
+        for cause in &self.chain {
+            write!(f, ": {cause}")?;
+        }
+        for label in &self.labels {
+            write!(
+                f,
+                "\n  {}: {}\n    {}",
+                label.location, label.message, label.snippet
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Shader files that changed since the passes were last built.
+#[derive(Clone, Debug)]
+pub enum ShaderChanges {
+    /// Rebuild everything, for instance after the library was reloaded.
+    All,
+    /// Files changed in the shader folder, relative to it.
+    Files(Vec<String>),
+}
+
+// Passed by the runner, see `layout_fingerprint` in `lib.rs`.
+crate::impl_type_layout!(enum ShaderChanges {
+    All,
+    Files(files: Vec<String>),
+});
+
+impl ShaderChanges {
+    /// Whether the shader `name` needs to be rebuilt,
+    /// ie. if it or one of the files it imports changed.
+    /// Shaders that were not built successfully yet are always affected.
+    pub fn affects(&self, name: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Files(files) => crate::shader_builder::shader_dependencies(name)
+                .is_none_or(|dependencies| files.iter().any(|file| dependencies.contains(file))),
+        }
+    }
+}
+
+/// Passes rebuilt together by [`PipelineFuncs::update_passes`].
+///
+/// Every affected pass is built before any is replaced, so a shader failing to compile
+/// keeps all the previous passes rather than pairing new passes with old ones.
+///
+/// ```ignore
+/// let mut rebuild = PassRebuild::new(changes);
+/// rebuild.rebuild(&mut self.compute_pass, COMPUTE_SHADER, || Self::create_compute_pass(device))?;
+/// rebuild.rebuild(&mut self.render_pass, DRAW_SHADER, || Self::create_render_pass(device))?;
+/// rebuild.commit();
+/// ```
+pub struct PassRebuild<'a> {
+    changes: &'a ShaderChanges,
+    /// Replace a pass by its rebuilt version.
+    commits: Vec<Box<dyn FnOnce() + 'a>>,
+}
+
+impl<'a> PassRebuild<'a> {
+    pub fn new(changes: &'a ShaderChanges) -> Self {
+        Self {
+            changes,
+            commits: vec![],
+        }
+    }
+
+    /// Build a new version of `pass` if `shader` is affected by the changes.
+    /// `pass` is only replaced by [`Self::commit`].
+    ///
+    /// # Errors
+    /// - The error of `build`, the passes rebuilt so far being dropped.
+    pub fn rebuild<T: 'a>(
+        &mut self,
+        pass: &'a mut T,
+        shader: &str,
+        build: impl FnOnce() -> Result<T, PipelineError>,
+    ) -> Result<(), PipelineError> {
+        if self.changes.affects(shader) {
+            let rebuilt = build()?;
+            self.commits.push(Box::new(move || *pass = rebuilt));
+        }
+        Ok(())
+    }
+
+    /// Replace every rebuilt pass.
+    pub fn commit(self) {
+        for commit in self.commits {
+            commit();
+        }
+    }
+}
+
 /// PipelineFuncs trait.
 ///
 /// All shader pipelines (ie specific projects) should implement this trait.
-pub trait PipelineFuncs: Sized {
+///
+/// Functions that do not take the pipeline are only available on concrete types,
+/// so a running pipeline can be used as a `dyn PipelineFuncs`, see [`crate::pipeline_registry`].
+pub trait PipelineFuncs {
     /// Create pipeline.
     ///
     /// # Errors
@@ -46,12 +439,19 @@ pub trait PipelineFuncs: Sized {
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
         surface_configuration: &wgpu::SurfaceConfiguration,
-    ) -> Result<Self, PipelineError>;
+    ) -> Result<Self, PipelineError>
+    where
+        Self: Sized;
 
     /// Get pipeline name.
-    fn get_name() -> &'static str;
+    fn get_name() -> &'static str
+    where
+        Self: Sized;
 
-    /// Create render pipeline.
+    /// Recreate the passes using shaders affected by `changes`.
+    ///
+    /// If any pass fails to build, every pass must be left unchanged:
+    /// build them with a [`PassRebuild`] and only commit it once they all succeeded.
     ///
     /// # Errors
     /// - `PipelineError::ShaderParseError` when the shader could not be compiled.
@@ -60,6 +460,7 @@ pub trait PipelineFuncs: Sized {
         surface: &wgpu::Surface,
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
+        changes: &ShaderChanges,
     ) -> Result<(), PipelineError>;
 
     /// Resize output
@@ -84,20 +485,32 @@ pub trait PipelineFuncs: Sized {
         false
     }
 
-    fn optional_features() -> wgpu::Features {
+    fn optional_features() -> wgpu::Features
+    where
+        Self: Sized,
+    {
         wgpu::Features::empty()
     }
-    fn required_features() -> wgpu::Features {
+    fn required_features() -> wgpu::Features
+    where
+        Self: Sized,
+    {
         wgpu::Features::empty()
     }
-    fn required_downlevel_capabilities() -> wgpu::DownlevelCapabilities {
+    fn required_downlevel_capabilities() -> wgpu::DownlevelCapabilities
+    where
+        Self: Sized,
+    {
         wgpu::DownlevelCapabilities {
             flags: wgpu::DownlevelFlags::empty(),
             shader_model: wgpu::ShaderModel::Sm5,
             ..wgpu::DownlevelCapabilities::default()
         }
     }
-    fn required_limits() -> wgpu::Limits {
+    fn required_limits() -> wgpu::Limits
+    where
+        Self: Sized,
+    {
         // These downlevel limits will allow the code to run on all possible hardware
         wgpu::Limits::downlevel_webgl2_defaults()
     }
@@ -105,4 +518,79 @@ pub trait PipelineFuncs: Sized {
     fn get_camera(&mut self) -> Option<&mut crate::camera_control::CameraLookAt> {
         None
     }
+
+    /// Passes to rebuild because the pipeline changed them itself,
+    /// for instance when an `override` constant was edited in the ui.
+    /// Polled by the runner before each frame.
+    fn take_pass_updates(&mut self) -> Option<ShaderChanges> {
+        None
+    }
+
+    /// Save the state to keep across library reloads, such as ui settings and the camera.
+    ///
+    /// The runner drops the pipeline before the library is unloaded, since its layout may change,
+    /// and creates a new one with `init` followed by [`Self::import_state`] once it is reloaded.
+    fn export_state(&self, _state: &mut PipelineState) {}
+
+    /// Restore the state saved by [`Self::export_state`], possibly by an older version of the library.
+    /// Values it did not save keep their `init` value.
+    fn import_state(&mut self, _state: &PipelineState) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker() {
+        let location = |column, length| ShaderLocation {
+            file: "draw.wgsl".to_owned(),
+            line: 1,
+            column,
+            length,
+        };
+        assert_eq!(location(5, 3).marker("let abc = 1;"), "    ^^^");
+        // `é` takes two bytes but a single column.
+        assert_eq!(location(11, 3).marker("// é let abc;"), "         ^^^");
+        assert_eq!(location(1, 0).marker(""), "^");
+    }
+
+    #[test]
+    fn test_pass_rebuild() {
+        let mut first_pass = "compute v1".to_owned();
+        let mut second_pass = "render v1".to_owned();
+
+        // The second pass fails, the first one is not replaced.
+        let mut rebuild = PassRebuild::new(&ShaderChanges::All);
+        rebuild
+            .rebuild(&mut first_pass, "demos/boids/compute.wgsl", || {
+                Ok("compute v2".to_owned())
+            })
+            .unwrap();
+        let result = rebuild.rebuild(&mut second_pass, "demos/boids/draw.wgsl", || {
+            Err(PipelineError::ShaderNotFound {
+                file: "demos/boids/draw.wgsl".to_owned(),
+            })
+        });
+        assert!(matches!(result, Err(PipelineError::ShaderNotFound { .. })));
+        drop(rebuild);
+        assert_eq!(first_pass, "compute v1");
+        assert_eq!(second_pass, "render v1");
+
+        // Both passes succeed, they are replaced on commit.
+        let mut rebuild = PassRebuild::new(&ShaderChanges::All);
+        rebuild
+            .rebuild(&mut first_pass, "demos/boids/compute.wgsl", || {
+                Ok("compute v2".to_owned())
+            })
+            .unwrap();
+        rebuild
+            .rebuild(&mut second_pass, "demos/boids/draw.wgsl", || {
+                Ok("render v2".to_owned())
+            })
+            .unwrap();
+        rebuild.commit();
+        assert_eq!(first_pass, "compute v2");
+        assert_eq!(second_pass, "render v2");
+    }
 }
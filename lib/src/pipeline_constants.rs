@@ -0,0 +1,454 @@
+//! Values of the wgsl `override` declarations of a shader, set when creating a pipeline.
+//!
+//! Overrides are reflected from the module, so the values can be edited in the ui
+//! without listing the constants in rust:
+//!
+//! ```wgsl
+//! override brightness: f32 = 1.0;
+//! @id(0) override grayscale: bool = false;
+//! ```
+//!
+//! Constants are keyed by their `@id` if they have one, by their name otherwise, like wgpu expects.
+
+use std::collections::HashMap;
+
+use crate::pipeline::PipelineError;
+use crate::pipeline_state::PipelineState;
+
+/// Type of an `override` declaration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverrideType {
+    Bool,
+    I32,
+    U32,
+    F32,
+}
+
+crate::impl_type_layout!(
+    enum OverrideType {
+        Bool,
+        I32,
+        U32,
+        F32,
+    }
+);
+
+/// `override` declaration reflected from a shader.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverrideConstant {
+    /// Key of the constant in the pipeline constants: its `@id`, or its name.
+    pub key: String,
+    pub name: String,
+    pub ty: OverrideType,
+    /// Default value, if the shader initializes the constant with a literal.
+    pub default: Option<f64>,
+    /// Whether the shader has no default value, in which case a value must be provided.
+    pub required: bool,
+}
+
+crate::impl_type_layout!(OverrideConstant {
+    key,
+    name,
+    ty,
+    default,
+    required
+});
+
+/// Override constants of a shader and the values they are set to.
+#[derive(Clone, Debug)]
+pub struct PipelineConstants {
+    shader: String,
+    constants: Vec<OverrideConstant>,
+    values: HashMap<String, f64>,
+}
+
+crate::impl_type_layout!(PipelineConstants {
+    shader,
+    constants,
+    values
+});
+
+impl PipelineConstants {
+    /// Reflect the override constants of a validated module and set them to `values`.
+    ///
+    /// Values of constants the shader does not declare anymore are dropped with a warning,
+    /// so removing an override from the shader does not fail the hot reload.
+    ///
+    /// # Errors
+    /// - [`PipelineError::PipelineConstantMismatch`] if a constant without default has no value,
+    ///   or if a value does not fit the type of its constant.
+    pub fn reflect(
+        shader: &str,
+        module: &naga::Module,
+        values: HashMap<String, f64>,
+    ) -> Result<Self, PipelineError> {
+        let mut constants = vec![];
+        let mut mismatches = vec![];
+        for (_, constant) in module.overrides.iter() {
+            let name = constant.name.clone().unwrap_or_default();
+            let key = constant
+                .id
+                .map_or_else(|| name.clone(), |id| id.to_string());
+            let ty = match module.types[constant.ty].inner {
+                naga::TypeInner::Scalar(naga::Scalar::BOOL) => OverrideType::Bool,
+                naga::TypeInner::Scalar(naga::Scalar::I32) => OverrideType::I32,
+                naga::TypeInner::Scalar(naga::Scalar::U32) => OverrideType::U32,
+                naga::TypeInner::Scalar(naga::Scalar::F32) => OverrideType::F32,
+                _ => {
+                    mismatches.push(format!("`{name}` has a type wgpu cannot override"));
+                    continue;
+                }
+            };
+            let default = constant
+                .init
+                .and_then(|init| match module.global_expressions[init] {
+                    naga::Expression::Literal(literal) => literal_value(literal),
+                    _ => None,
+                });
+            constants.push(OverrideConstant {
+                key,
+                name,
+                ty,
+                default,
+                required: constant.init.is_none(),
+            });
+        }
+
+        let mut known_values = HashMap::new();
+        for constant in &constants {
+            match values.get(&constant.key) {
+                Some(&value) => {
+                    if let Some(error) = value_error(constant, value) {
+                        mismatches.push(error);
+                    }
+                    known_values.insert(constant.key.clone(), value);
+                }
+                None if constant.required => mismatches.push(format!(
+                    "`{}` has no default value and was not provided one",
+                    constant.name
+                )),
+                None => {}
+            }
+        }
+        for key in values.keys() {
+            if !known_values.contains_key(key) {
+                log::warn!("{shader} has no override constant `{key}`, dropping its value");
+            }
+        }
+
+        if !mismatches.is_empty() {
+            return Err(PipelineError::PipelineConstantMismatch {
+                shader: shader.to_owned(),
+                mismatches,
+            });
+        }
+
+        Ok(Self {
+            shader: shader.to_owned(),
+            constants,
+            values: known_values,
+        })
+    }
+
+    /// Shader the constants were reflected from.
+    pub fn shader(&self) -> &str {
+        &self.shader
+    }
+
+    /// Override declarations of the shader, in declaration order.
+    pub fn constants(&self) -> &[OverrideConstant] {
+        &self.constants
+    }
+
+    /// Values passed to the pipeline, constants without value keep their default.
+    pub fn values(&self) -> &HashMap<String, f64> {
+        &self.values
+    }
+
+    /// Value the pipeline uses for `constant`.
+    pub fn value(&self, constant: &OverrideConstant) -> f64 {
+        self.values
+            .get(&constant.key)
+            .copied()
+            .or(constant.default)
+            .unwrap_or_default()
+    }
+
+    /// Compilation options setting the constants, for stages created by hand.
+    pub fn compilation_options(&self) -> wgpu::PipelineCompilationOptions<'_> {
+        wgpu::PipelineCompilationOptions {
+            constants: &self.values,
+            ..Default::default()
+        }
+    }
+
+    /// Create a render pipeline, setting the constants in both its vertex and fragment stages.
+    pub fn create_render_pipeline(
+        &self,
+        device: &wgpu::Device,
+        descriptor: &wgpu::RenderPipelineDescriptor,
+    ) -> wgpu::RenderPipeline {
+        let mut descriptor = descriptor.clone();
+        descriptor.vertex.compilation_options = self.compilation_options();
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.compilation_options = self.compilation_options();
+        }
+        device.create_render_pipeline(&descriptor)
+    }
+
+    /// Create a compute pipeline, setting the constants.
+    pub fn create_compute_pipeline(
+        &self,
+        device: &wgpu::Device,
+        descriptor: &wgpu::ComputePipelineDescriptor,
+    ) -> wgpu::ComputePipeline {
+        let mut descriptor = descriptor.clone();
+        descriptor.compilation_options = self.compilation_options();
+        device.create_compute_pipeline(&descriptor)
+    }
+
+    /// Save the values edited in the ui under `prefix`, see `PipelineFuncs::export_state`.
+    pub fn export_state(&self, state: &mut PipelineState, prefix: &str) {
+        for (key, value) in &self.values {
+            state.set(&format!("{prefix}{key}"), *value);
+        }
+    }
+
+    /// Restore the values saved by [`Self::export_state`], skipping the ones that do not fit
+    /// the constants of the shader anymore.
+    /// Return true if a value was restored, in which case the pipeline should be recreated.
+    pub fn import_state(&mut self, state: &PipelineState, prefix: &str) -> bool {
+        let mut changed = false;
+        for constant in &self.constants {
+            let Some(value) = state.get::<f64>(&format!("{prefix}{}", constant.key)) else {
+                continue;
+            };
+            if let Some(error) = value_error(constant, value) {
+                log::warn!("{}: {error}, not restoring it", self.shader);
+                continue;
+            }
+            self.values.insert(constant.key.clone(), value);
+            changed = true;
+        }
+        changed
+    }
+
+    /// Draw a control for each constant.
+    /// Return true if a value changed, in which case the pipeline should be recreated.
+    pub fn draw_ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        for constant in &self.constants {
+            let mut value = self.value(constant);
+            let response = match constant.ty {
+                OverrideType::Bool => {
+                    let mut checked = value != 0.0;
+                    let response = ui.checkbox(&mut checked, &constant.name);
+                    value = if checked { 1.0 } else { 0.0 };
+                    response
+                }
+                OverrideType::I32 | OverrideType::U32 => {
+                    let (min, max) = integer_range(constant.ty);
+                    ui.horizontal(|ui| {
+                        let response = ui.add(
+                            egui::DragValue::new(&mut value)
+                                .range(min..=max)
+                                .fixed_decimals(0),
+                        );
+                        ui.label(&constant.name);
+                        response
+                    })
+                    .inner
+                }
+                OverrideType::F32 => {
+                    ui.horizontal(|ui| {
+                        let response = ui.add(egui::DragValue::new(&mut value).speed(0.01));
+                        ui.label(&constant.name);
+                        response
+                    })
+                    .inner
+                }
+            };
+            if response.changed() {
+                if matches!(constant.ty, OverrideType::I32 | OverrideType::U32) {
+                    value = value.round();
+                }
+                self.values.insert(constant.key.clone(), value);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Value of a literal initializing an override constant.
+fn literal_value(literal: naga::Literal) -> Option<f64> {
+    match literal {
+        naga::Literal::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+        naga::Literal::I32(value) => Some(value.into()),
+        naga::Literal::U32(value) => Some(value.into()),
+        naga::Literal::F32(value) => Some(value.into()),
+        naga::Literal::F64(value) | naga::Literal::AbstractFloat(value) => Some(value),
+        naga::Literal::AbstractInt(value) => Some(value as f64),
+        naga::Literal::I64(_) | naga::Literal::U64(_) => None,
+    }
+}
+
+/// Range of the values an integer constant can hold.
+fn integer_range(ty: OverrideType) -> (f64, f64) {
+    match ty {
+        OverrideType::I32 => (i32::MIN.into(), i32::MAX.into()),
+        OverrideType::U32 => (0.0, u32::MAX.into()),
+        OverrideType::Bool | OverrideType::F32 => (f64::MIN, f64::MAX),
+    }
+}
+
+/// Why `value` cannot be converted to the type of `constant`, if it cannot.
+fn value_error(constant: &OverrideConstant, value: f64) -> Option<String> {
+    let name = &constant.name;
+    match constant.ty {
+        _ if !value.is_finite() => Some(format!("`{name}` is set to {value}, which is not finite")),
+        OverrideType::Bool => None,
+        OverrideType::F32 => (value.abs() > f32::MAX.into())
+            .then(|| format!("`{name}` is set to {value}, which does not fit in a f32")),
+        OverrideType::I32 | OverrideType::U32 => {
+            let (min, max) = integer_range(constant.ty);
+            if value.fract() != 0.0 {
+                Some(format!(
+                    "`{name}` is set to {value}, which is not an integer"
+                ))
+            } else if value < min || value > max {
+                Some(format!(
+                    "`{name}` is set to {value}, which does not fit in a {}",
+                    if constant.ty == OverrideType::I32 {
+                        "i32"
+                    } else {
+                        "u32"
+                    }
+                ))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ShaderBuilderForLibrary, ShaderDefines};
+
+    const WGSL: &str = "
+        override scale: f32 = 1.5;
+        @id(3) override enabled: bool = true;
+        override count: u32;
+        override offset: i32 = -2;
+        override doubled: f32 = scale * 2.0;
+    ";
+
+    fn reflect(wgsl: &str, values: &[(&str, f64)]) -> Result<PipelineConstants, PipelineError> {
+        let module = naga::front::wgsl::parse_str(wgsl).unwrap();
+        let values = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), *value))
+            .collect();
+        PipelineConstants::reflect("test.wgsl", &module, values)
+    }
+
+    fn mismatches(wgsl: &str, values: &[(&str, f64)]) -> Vec<String> {
+        match reflect(wgsl, values) {
+            Ok(_) => vec![],
+            Err(PipelineError::PipelineConstantMismatch { mismatches, .. }) => mismatches,
+            Err(error) => panic!("unexpected error {error}"),
+        }
+    }
+
+    #[test]
+    fn test_reflect_constants() {
+        let constants = reflect(WGSL, &[("count", 4.0), ("removed", 1.0)]).unwrap();
+        let reflected: Vec<_> = constants
+            .constants()
+            .iter()
+            .map(|constant| (constant.key.as_str(), constant.ty, constant.default))
+            .collect();
+        assert_eq!(
+            reflected,
+            [
+                ("scale", OverrideType::F32, Some(1.5)),
+                ("3", OverrideType::Bool, Some(1.0)),
+                ("count", OverrideType::U32, None),
+                ("offset", OverrideType::I32, Some(-2.0)),
+                // Only literals are reflected, the value is computed by wgpu.
+                ("doubled", OverrideType::F32, None),
+            ]
+        );
+
+        // Unknown keys are dropped, constants without value use their default.
+        assert_eq!(
+            constants.values(),
+            &HashMap::from([("count".to_owned(), 4.0)])
+        );
+        let values: Vec<_> = constants
+            .constants()
+            .iter()
+            .map(|constant| constants.value(constant))
+            .collect();
+        assert_eq!(values, [1.5, 1.0, 4.0, -2.0, 0.0]);
+    }
+
+    #[test]
+    fn test_constant_mismatch() {
+        assert_eq!(
+            mismatches(WGSL, &[("offset", 0.5), ("scale", f64::NAN)]),
+            [
+                "`scale` is set to NaN, which is not finite",
+                "`count` has no default value and was not provided one",
+                "`offset` is set to 0.5, which is not an integer",
+            ]
+        );
+        assert_eq!(
+            mismatches(WGSL, &[("count", -1.0)]),
+            ["`count` is set to -1, which does not fit in a u32"]
+        );
+        assert!(mismatches(WGSL, &[("count", 0.0), ("3", 0.0)]).is_empty());
+    }
+
+    #[test]
+    fn test_constants_state() {
+        let mut constants = reflect(WGSL, &[("count", 4.0), ("scale", 0.5)]).unwrap();
+        let mut state = PipelineState::new();
+        constants.export_state(&mut state, "constants.");
+        // The shader now declares `offset` as a u32.
+        state.set("constants.offset", -1.0);
+
+        let mut reloaded = reflect(
+            &WGSL.replace("offset: i32 = -2", "offset: u32 = 2u"),
+            &[("count", 1.0)],
+        )
+        .unwrap();
+        assert!(reloaded.import_state(&state, "constants."));
+        assert_eq!(
+            reloaded.values(),
+            &HashMap::from([("count".to_owned(), 4.0), ("scale".to_owned(), 0.5)])
+        );
+        assert!(!constants.import_state(&PipelineState::new(), "constants."));
+    }
+
+    #[test]
+    fn test_demo_constants() {
+        let module =
+            ShaderBuilderForLibrary::validate("demos/polygon/draw.wgsl", &ShaderDefines::new())
+                .unwrap();
+        let constants = PipelineConstants::reflect(
+            "demos/polygon/draw.wgsl",
+            &module,
+            HashMap::from([("brightness".to_owned(), 0.5)]),
+        )
+        .unwrap();
+        let names: Vec<_> = constants
+            .constants()
+            .iter()
+            .map(|constant| constant.name.as_str())
+            .collect();
+        assert_eq!(names, ["brightness", "grayscale"]);
+    }
+}
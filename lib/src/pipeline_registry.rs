@@ -0,0 +1,284 @@
+//! Pipelines the runner can switch between at runtime.
+//!
+//! The running pipeline is a [`CurrentPipeline`], an enum over every registered pipeline,
+//! so switching does not need a rebuild. Its functions dispatch inside the library:
+//! unlike a boxed trait object, it holds no vtable pointing into a library that was unloaded.
+//!
+//! The library hands the pipeline to the runner as a [`PipelineHandle`].
+//!
+//! Add a pipeline to the `register_pipelines!` invocation below, and list its fields
+//! with [`impl_type_layout!`](crate::impl_type_layout).
+
+use std::ptr::NonNull;
+
+use crate::demo_pipelines;
+use crate::pipeline::{PipelineError, PipelineFuncs};
+use crate::type_layout::{describe_variant_field, LayoutDescription, TypeLayout};
+
+/// Generate [`CurrentPipeline`] and the registry from the pipeline types.
+macro_rules! register_pipelines {
+    ($($variant:ident => $pipeline:ty),+ $(,)?) => {
+        /// Running pipeline, one of the registered ones.
+        pub enum CurrentPipeline {
+            $($variant($pipeline)),+
+        }
+
+        /// Registered pipelines, the first one being created at startup if the adapter supports it.
+        pub fn pipelines() -> Vec<PipelineInfo> {
+            vec![$(PipelineInfo::of::<$pipeline>()),+]
+        }
+
+        impl CurrentPipeline {
+            /// Create the pipeline named `name`.
+            ///
+            /// # Errors
+            /// - The error of the pipeline `init`.
+            /// - [`PipelineError::UnknownPipeline`] if no pipeline has this name.
+            pub fn init(
+                name: &str,
+                surface: &wgpu::Surface,
+                device: &wgpu::Device,
+                adapter: &wgpu::Adapter,
+                surface_configuration: &wgpu::SurfaceConfiguration,
+            ) -> Result<Self, PipelineError> {
+                $(
+                    if name == <$pipeline>::get_name() {
+                        return <$pipeline>::init(surface, device, adapter, surface_configuration)
+                            .map(Self::$variant);
+                    }
+                )+
+                Err(PipelineError::UnknownPipeline {
+                    name: name.to_owned(),
+                })
+            }
+
+            /// Name of the running pipeline.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant(_) => <$pipeline>::get_name()),+
+                }
+            }
+
+            pub fn funcs(&self) -> &dyn PipelineFuncs {
+                match self {
+                    $(Self::$variant(pipeline) => pipeline),+
+                }
+            }
+
+            pub fn funcs_mut(&mut self) -> &mut dyn PipelineFuncs {
+                match self {
+                    $(Self::$variant(pipeline) => pipeline),+
+                }
+            }
+        }
+
+        /// Layout of the enum and of every registered pipeline,
+        /// so changing any of them changes the fingerprint.
+        impl TypeLayout for CurrentPipeline {
+            fn describe() -> LayoutDescription {
+                LayoutDescription {
+                    fields: vec![
+                        $(describe_variant_field::<$pipeline>(concat!(stringify!($variant), ".0"))),+
+                    ],
+                    variants: vec![$(stringify!($variant)),+],
+                    ..LayoutDescription::opaque::<Self>()
+                }
+            }
+        }
+    };
+}
+
+register_pipelines! {
+    Polygon => demo_pipelines::polygon::Pipeline,
+    Boids => demo_pipelines::boids::Pipeline,
+    Raymarching => demo_pipelines::raymarching::Pipeline,
+}
+
+/// [`CurrentPipeline`] owned by the library, held by the runner as an opaque pointer.
+///
+/// The size and drop glue of the enum come from the library that created it, and change with a reload:
+/// the runner never moves, sizes or drops it, only the library functions dereference the handle
+/// and [`drop_pipeline`](crate::drop_pipeline) frees it. Dropping the handle itself leaks the pipeline.
+#[must_use = "dropping the handle leaks the pipeline, free it with `drop_pipeline`"]
+pub struct PipelineHandle(NonNull<CurrentPipeline>);
+
+impl PipelineHandle {
+    pub(crate) fn new(pipeline: CurrentPipeline) -> Self {
+        Self(NonNull::from(Box::leak(Box::new(pipeline))))
+    }
+
+    pub(crate) fn get(&self) -> &CurrentPipeline {
+        // SAFETY: the pointer comes from `Box::leak` and is only freed by `into_box`, which consumes the handle.
+        unsafe { self.0.as_ref() }
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut CurrentPipeline {
+        // SAFETY: see `get`, the handle is borrowed mutably.
+        unsafe { self.0.as_mut() }
+    }
+
+    pub(crate) fn into_box(self) -> Box<CurrentPipeline> {
+        // SAFETY: see `get`, the handle is consumed so the pointer cannot be used again.
+        unsafe { Box::from_raw(self.0.as_ptr()) }
+    }
+}
+
+/// Registered pipeline and what it requires from the device.
+/// Owns its name since a `&'static str` cannot outlive a library reload.
+#[derive(Clone, Debug)]
+pub struct PipelineInfo {
+    pub name: String,
+    /// Features requested if the adapter supports them.
+    pub optional_features: wgpu::Features,
+    pub required_features: wgpu::Features,
+    pub required_downlevel_capabilities: wgpu::DownlevelCapabilities,
+    pub required_limits: wgpu::Limits,
+}
+
+// Returned to the runner, see `layout_fingerprint` in `lib.rs`.
+crate::impl_type_layout!(PipelineInfo {
+    name,
+    optional_features,
+    required_features,
+    required_downlevel_capabilities,
+    required_limits,
+});
+
+impl PipelineInfo {
+    pub fn of<T: PipelineFuncs>() -> Self {
+        Self {
+            name: T::get_name().to_owned(),
+            optional_features: T::optional_features(),
+            required_features: T::required_features(),
+            required_downlevel_capabilities: T::required_downlevel_capabilities(),
+            required_limits: T::required_limits(),
+        }
+    }
+
+    /// Why the pipeline cannot run with these features, capabilities and limits,
+    /// `None` if it can.
+    pub fn unsupported_reason(
+        &self,
+        features: wgpu::Features,
+        downlevel_capabilities: &wgpu::DownlevelCapabilities,
+        limits: &wgpu::Limits,
+    ) -> Option<String> {
+        let mut reasons = vec![];
+        if !features.contains(self.required_features) {
+            reasons.push(format!(
+                "missing features {:?}",
+                self.required_features - features
+            ));
+        }
+        let required = &self.required_downlevel_capabilities;
+        if downlevel_capabilities.shader_model < required.shader_model {
+            reasons.push(format!("requires shader model {:?}", required.shader_model));
+        }
+        if !downlevel_capabilities.flags.contains(required.flags) {
+            reasons.push(format!(
+                "missing downlevel capabilities {:?}",
+                required.flags - downlevel_capabilities.flags
+            ));
+        }
+        self.required_limits
+            .check_limits_with_fail_fn(limits, false, |name, required, allowed| {
+                reasons.push(format!("requires {name} {required}, allowed {allowed}"));
+            });
+        (!reasons.is_empty()).then(|| reasons.join("\n"))
+    }
+}
+
+/// Limits to request so every pipeline in `pipelines` can run on the same device:
+/// the highest of their limits, as long as the others fit in them.
+/// Pipelines that do not fit are reported by [`PipelineInfo::unsupported_reason`].
+pub fn device_limits(pipelines: &[PipelineInfo]) -> wgpu::Limits {
+    pipelines
+        .iter()
+        .map(|pipeline| &pipeline.required_limits)
+        .fold(
+            None,
+            |limits: Option<&wgpu::Limits>, required| match limits {
+                Some(limits) if !limits.check_limits(required) => Some(limits),
+                _ => Some(required),
+            },
+        )
+        .cloned()
+        .unwrap_or_else(wgpu::Limits::downlevel_webgl2_defaults)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webgl2() -> (wgpu::Features, wgpu::DownlevelCapabilities, wgpu::Limits) {
+        (
+            wgpu::Features::empty(),
+            wgpu::DownlevelCapabilities {
+                flags: wgpu::DownlevelFlags::empty(),
+                ..Default::default()
+            },
+            wgpu::Limits::downlevel_webgl2_defaults(),
+        )
+    }
+
+    #[test]
+    fn test_registry() {
+        let pipelines = pipelines();
+        let names: Vec<_> = pipelines
+            .iter()
+            .map(|pipeline| pipeline.name.as_str())
+            .collect();
+        assert_eq!(names, ["demo polygon", "demo boids", "demo raymarching"]);
+        assert_eq!(CurrentPipeline::describe().variants.len(), pipelines.len());
+
+        // Boids needs compute shaders, which webgl2 does not have.
+        let (features, downlevel_capabilities, limits) = webgl2();
+        let unsupported: Vec<_> = pipelines
+            .iter()
+            .filter_map(|pipeline| {
+                pipeline
+                    .unsupported_reason(features, &downlevel_capabilities, &limits)
+                    .map(|reason| (pipeline.name.as_str(), reason))
+            })
+            .collect();
+        assert_eq!(unsupported.len(), 1);
+        assert_eq!(unsupported[0].0, "demo boids");
+        assert!(unsupported[0].1.contains("COMPUTE_SHADERS"));
+        assert!(unsupported[0]
+            .1
+            .contains("max_storage_buffers_per_shader_stage"));
+
+        assert!(pipelines.iter().all(|pipeline| pipeline
+            .unsupported_reason(
+                features,
+                &wgpu::DownlevelCapabilities::default(),
+                &wgpu::Limits::default(),
+            )
+            .is_none()));
+    }
+
+    #[test]
+    fn test_device_limits() {
+        let pipelines = pipelines();
+        assert_eq!(
+            device_limits(&pipelines),
+            wgpu::Limits::downlevel_defaults()
+        );
+        assert_eq!(
+            device_limits(&[pipelines[0].clone(), pipelines[2].clone()]),
+            wgpu::Limits::downlevel_webgl2_defaults()
+        );
+
+        // Neither fits in the other: the first one is kept.
+        let mut textures = pipelines[0].clone();
+        textures.required_limits.max_texture_dimension_2d = 16384;
+        let mut buffers = pipelines[0].clone();
+        buffers.required_limits.max_storage_buffers_per_shader_stage = 8;
+        let limits = device_limits(&[textures.clone(), buffers.clone()]);
+        assert_eq!(limits, textures.required_limits);
+        let (features, downlevel_capabilities, _) = webgl2();
+        assert!(buffers
+            .unsupported_reason(features, &downlevel_capabilities, &limits)
+            .is_some());
+    }
+}
@@ -0,0 +1,173 @@
+//! State of a pipeline kept across library reloads, see `PipelineFuncs::export_state`.
+//!
+//! A reloaded library may have changed the layout of the pipeline and its settings,
+//! so the old pipeline is dropped before the library is unloaded and a new one is created.
+//! The state crosses the reload as text, one `key = value` line per value:
+//! values the new version does not read are ignored, and values the old version
+//! did not write keep their `init` value.
+
+use std::collections::BTreeMap;
+
+/// Value saved in a [`PipelineState`], written as text so it does not depend on the layout.
+pub trait StateValue: Sized {
+    fn to_text(&self) -> String;
+    fn from_text(text: &str) -> Option<Self>;
+}
+
+macro_rules! impl_state_value {
+    ($($ty:ty),*) => {
+        $(
+            impl StateValue for $ty {
+                fn to_text(&self) -> String {
+                    // Floats are displayed with the shortest text that parses back to them.
+                    self.to_string()
+                }
+
+                fn from_text(text: &str) -> Option<Self> {
+                    text.parse().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_state_value!(bool, i32, u32, f32, f64);
+
+/// Arrays are written as space separated values.
+impl<T: StateValue, const N: usize> StateValue for [T; N] {
+    fn to_text(&self) -> String {
+        self.iter()
+            .map(StateValue::to_text)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        let values = text
+            .split_whitespace()
+            .map(T::from_text)
+            .collect::<Option<Vec<_>>>()?;
+        values.try_into().ok()
+    }
+}
+
+/// Values saved by a pipeline before the library reloads, by key.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PipelineState {
+    values: BTreeMap<String, String>,
+}
+
+// Exported and imported by the runner across reloads, see `layout_fingerprint` in `lib.rs`.
+crate::impl_type_layout!(PipelineState { values });
+
+impl PipelineState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Save `value` under `key`. Keys cannot contain `=` or line breaks.
+    pub fn set(&mut self, key: &str, value: impl StateValue) {
+        debug_assert!(
+            !key.contains(['=', '\n']),
+            "invalid pipeline state key {key}"
+        );
+        self.values.insert(key.to_owned(), value.to_text());
+    }
+
+    /// Value saved under `key`, `None` if there is none or if it does not parse as a `T`.
+    pub fn get<T: StateValue>(&self, key: &str) -> Option<T> {
+        let text = self.values.get(key)?;
+        let value = T::from_text(text);
+        if value.is_none() {
+            log::warn!("pipeline state `{key}` has an invalid value `{text}`, ignoring it");
+        }
+        value
+    }
+
+    /// Overwrite `value` with the value saved under `key`, if there is one.
+    pub fn restore<T: StateValue>(&self, key: &str, value: &mut T) {
+        if let Some(saved) = self.get(key) {
+            *value = saved;
+        }
+    }
+
+    /// Keys starting with `prefix`, without it.
+    pub fn keys_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        self.values
+            .keys()
+            .filter_map(move |key| key.strip_prefix(prefix))
+    }
+
+    /// Serialize the state, to pass it to the reloaded library.
+    pub fn to_blob(&self) -> String {
+        self.values
+            .iter()
+            .map(|(key, value)| format!("{key} = {value}\n"))
+            .collect()
+    }
+
+    /// Parse a state serialized by [`Self::to_blob`], possibly by an older version of the library.
+    pub fn from_blob(blob: &str) -> Self {
+        let values = blob
+            .lines()
+            .filter_map(|line| {
+                let parsed = line.split_once(" = ");
+                if parsed.is_none() {
+                    log::warn!("invalid pipeline state line `{line}`, ignoring it");
+                }
+                parsed
+            })
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        Self { values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_blob() {
+        let mut state = PipelineState::new();
+        state.set("settings.speed", 0.1f32);
+        state.set("settings.count", 7u32);
+        state.set("settings.enabled", true);
+        state.set("camera.center", [1.5f32, -2.0, 0.0, 1e-7]);
+
+        let blob = state.to_blob();
+        assert_eq!(
+            blob,
+            "camera.center = 1.5 -2 0 0.0000001\n\
+             settings.count = 7\n\
+             settings.enabled = true\n\
+             settings.speed = 0.1\n"
+        );
+
+        let state = PipelineState::from_blob(&blob);
+        assert_eq!(state.get::<f32>("settings.speed"), Some(0.1));
+        assert_eq!(state.get::<u32>("settings.count"), Some(7));
+        assert_eq!(state.get::<bool>("settings.enabled"), Some(true));
+        assert_eq!(
+            state.get::<[f32; 4]>("camera.center"),
+            Some([1.5, -2.0, 0.0, 1e-7])
+        );
+        let settings: Vec<_> = state.keys_with_prefix("settings.").collect();
+        assert_eq!(settings, ["count", "enabled", "speed"]);
+    }
+
+    #[test]
+    fn test_state_changes() {
+        // The reloaded library added a field and changed the type of another one.
+        let state = PipelineState::from_blob("speed = 0.5\ncount = -1\nmalformed\n");
+        let mut speed = 1.0f32;
+        let mut count = 3u32;
+        let mut added = 2.0f32;
+        state.restore("speed", &mut speed);
+        state.restore("count", &mut count);
+        state.restore("added", &mut added);
+        assert_eq!((speed, count, added), (0.5, 3, 2.0));
+
+        assert_eq!(state.get::<[f32; 4]>("speed"), None);
+    }
+}
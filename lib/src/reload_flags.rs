@@ -3,6 +3,9 @@
 pub enum LibState {
     /// Library is stable: nothing to do
     Stable,
+    /// Library is about to reload: the runner saves the pipeline state and drops the pipeline,
+    /// then sets `Reloading` to let the reload happen
+    AboutToReload,
     /// Library is reloading: avoid calls to its function until it's done
     Reloading,
     /// Library is done reloading
@@ -10,7 +13,7 @@ pub enum LibState {
 }
 
 /// Reload flags contain the state of the library / shader folder
-/// `shaders` contains the shaders that were updated until last rebuild
+/// `shaders` contains the shaders that were updated until last rebuild, relative to the shader folder
 /// `lib` is the state of the library
 #[derive(Debug)]
 pub struct ReloadFlags {
@@ -0,0 +1,619 @@
+//! Bind group layouts reflected from a shader with naga,
+//! so they do not have to mirror the `@group` / `@binding` declarations by hand.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::pipeline::PipelineError;
+use crate::pipeline_constants::PipelineConstants;
+use crate::struct_layout::{check_struct_layout, LayoutRules, StructLayout};
+
+/// Resource declared by a shader with `@group(g) @binding(b)`.
+#[derive(Clone, Debug)]
+pub struct ShaderBinding {
+    /// Name of the global variable in the shader.
+    pub name: String,
+    /// Layout entry expected by the shader.
+    pub entry: wgpu::BindGroupLayoutEntry,
+}
+
+/// Resources declared by a shader, by group and binding.
+#[derive(Clone, Debug)]
+pub struct ShaderBindings {
+    /// Shader name, for error reporting.
+    shader: String,
+    groups: BTreeMap<u32, BTreeMap<u32, ShaderBinding>>,
+    /// Reflected module, to check the layout of its structs.
+    module: naga::Module,
+}
+
+impl ShaderBindings {
+    /// Reflect the resources declared by a validated module.
+    /// Resources no entry point uses are kept, visible to every stage of the shader,
+    /// so rust can keep binding them while their use is commented out during a hot reload.
+    ///
+    /// # Errors
+    /// - [`PipelineError::BindingMismatch`] if a resource cannot be expressed as a wgpu binding.
+    pub fn reflect(
+        shader: &str,
+        module: &naga::Module,
+        info: &naga::valid::ModuleInfo,
+    ) -> Result<Self, PipelineError> {
+        let error = |message: String| PipelineError::BindingMismatch {
+            shader: shader.to_owned(),
+            mismatches: vec![message],
+        };
+
+        let mut layouter = naga::proc::Layouter::default();
+        layouter
+            .update(module.to_ctx())
+            .map_err(|layout_error| error(layout_error.to_string()))?;
+
+        let stage = |stage| match stage {
+            naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+            naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+            naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+        };
+        let all_stages = module
+            .entry_points
+            .iter()
+            .fold(wgpu::ShaderStages::NONE, |stages, entry_point| {
+                stages | stage(entry_point.stage)
+            });
+        // Textures and samplers used together, to tell filtering ones apart.
+        let sampling: Vec<_> = (0..module.entry_points.len())
+            .flat_map(|index| &info.get_entry_point(index).sampling_set)
+            .map(|key| (key.image, key.sampler))
+            .collect();
+
+        let mut groups: BTreeMap<u32, BTreeMap<u32, ShaderBinding>> = BTreeMap::new();
+        for (handle, variable) in module.global_variables.iter() {
+            let Some(binding) = &variable.binding else {
+                continue;
+            };
+            let name = variable.name.clone().unwrap_or_default();
+
+            let mut visibility = wgpu::ShaderStages::NONE;
+            for (index, entry_point) in module.entry_points.iter().enumerate() {
+                if !info.get_entry_point(index)[handle].is_empty() {
+                    visibility |= stage(entry_point.stage);
+                }
+            }
+            if visibility.is_empty() {
+                visibility = all_stages;
+            }
+
+            // Arrays of resources are declared as `binding_array<T, N>`.
+            let (ty, count) = match module.types[variable.ty].inner {
+                naga::TypeInner::BindingArray { base, size } => match size {
+                    naga::ArraySize::Constant(size) => (base, Some(size)),
+                    naga::ArraySize::Dynamic => {
+                        return Err(error(format!(
+                            "{name}: binding arrays without a size are not supported"
+                        )))
+                    }
+                },
+                _ => (variable.ty, None),
+            };
+
+            let binding_type = match variable.space {
+                naga::AddressSpace::Uniform => wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(layouter[ty].size.into()),
+                },
+                naga::AddressSpace::Storage { access } => wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage {
+                        read_only: !access.contains(naga::StorageAccess::STORE),
+                    },
+                    has_dynamic_offset: false,
+                    // Runtime sized arrays count as a single element.
+                    min_binding_size: wgpu::BufferSize::new(layouter[ty].size.into()),
+                },
+                naga::AddressSpace::Handle => handle_binding_type(module, handle, ty, &sampling)
+                    .ok_or_else(|| error(format!("{name}: unsupported resource type")))?,
+                space => {
+                    return Err(error(format!(
+                        "{name}: unsupported address space {space:?} for a binding"
+                    )))
+                }
+            };
+
+            groups.entry(binding.group).or_default().insert(
+                binding.binding,
+                ShaderBinding {
+                    name,
+                    entry: wgpu::BindGroupLayoutEntry {
+                        binding: binding.binding,
+                        visibility,
+                        ty: binding_type,
+                        count,
+                    },
+                },
+            );
+        }
+
+        Ok(Self {
+            shader: shader.to_owned(),
+            groups,
+            module: module.clone(),
+        })
+    }
+
+    /// Check that the rust struct `T` has the layout of `wgsl_struct`, see [`check_struct_layout`].
+    ///
+    /// # Errors
+    /// - [`PipelineError::StructLayoutMismatch`] listing the differences.
+    pub fn check_struct_layout<T: StructLayout + bytemuck::Pod>(
+        &self,
+        wgsl_struct: &str,
+        rules: LayoutRules,
+    ) -> Result<(), PipelineError> {
+        check_struct_layout::<T>(&self.shader, &self.module, wgsl_struct, rules)
+    }
+
+    /// Reflect the `override` constants of the shader and set them to `values`,
+    /// see [`PipelineConstants::reflect`].
+    ///
+    /// # Errors
+    /// - [`PipelineError::PipelineConstantMismatch`] if `values` do not fit the declarations.
+    pub fn pipeline_constants(
+        &self,
+        values: HashMap<String, f64>,
+    ) -> Result<PipelineConstants, PipelineError> {
+        PipelineConstants::reflect(&self.shader, &self.module, values)
+    }
+
+    /// Bindings declared in `group`, by binding index.
+    pub fn group(&self, group: u32) -> impl Iterator<Item = &ShaderBinding> {
+        self.groups
+            .get(&group)
+            .into_iter()
+            .flat_map(BTreeMap::values)
+    }
+
+    /// Number of bind groups of the pipeline layout, including empty groups before the last one.
+    pub fn group_count(&self) -> u32 {
+        self.groups.keys().next_back().map_or(0, |group| group + 1)
+    }
+
+    /// Create the layouts of every bind group up to the last one declared by the shader.
+    pub fn create_bind_group_layouts(&self, device: &wgpu::Device) -> Vec<wgpu::BindGroupLayout> {
+        (0..self.group_count())
+            .map(|group| {
+                let entries: Vec<_> = self.group(group).map(|binding| binding.entry).collect();
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some(&format!("{} group {group}", self.shader)),
+                    entries: &entries,
+                })
+            })
+            .collect()
+    }
+
+    /// Create a pipeline layout from the layouts returned by `create_bind_group_layouts`.
+    pub fn create_pipeline_layout(
+        &self,
+        device: &wgpu::Device,
+        bind_group_layouts: &[wgpu::BindGroupLayout],
+    ) -> wgpu::PipelineLayout {
+        let bind_group_layouts: Vec<_> = bind_group_layouts.iter().collect();
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&self.shader),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        })
+    }
+
+    /// Check that `entries` bind exactly the resources the shader declares in `group`.
+    ///
+    /// # Errors
+    /// - [`PipelineError::BindingMismatch`] listing every missing, unused or incompatible binding.
+    pub fn check_bind_group(
+        &self,
+        group: u32,
+        entries: &[wgpu::BindGroupEntry],
+    ) -> Result<(), PipelineError> {
+        let mut mismatches = vec![];
+
+        for binding in self.group(group) {
+            let index = binding.entry.binding;
+            let description = format!("binding {index} of group {group} ({})", binding.name);
+            let Some(entry) = entries.iter().find(|entry| entry.binding == index) else {
+                mismatches.push(format!(
+                    "{description} is declared by the shader but not bound"
+                ));
+                continue;
+            };
+            if let Some(mismatch) = check_resource(&binding.entry.ty, &entry.resource) {
+                mismatches.push(format!("{description} {mismatch}"));
+            }
+        }
+
+        for entry in entries {
+            if !self
+                .group(group)
+                .any(|binding| binding.entry.binding == entry.binding)
+            {
+                mismatches.push(format!(
+                    "binding {} of group {group} is bound but not declared by the shader",
+                    entry.binding
+                ));
+            }
+        }
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(PipelineError::BindingMismatch {
+                shader: self.shader.clone(),
+                mismatches,
+            })
+        }
+    }
+
+    /// Create a bind group for `group`, after checking it matches the shader.
+    ///
+    /// # Errors
+    /// - [`PipelineError::BindingMismatch`] if `entries` do not match the shader.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        bind_group_layouts: &[wgpu::BindGroupLayout],
+        group: u32,
+        entries: &[wgpu::BindGroupEntry],
+    ) -> Result<wgpu::BindGroup, PipelineError> {
+        self.check_bind_group(group, entries)?;
+        let layout = bind_group_layouts.get(group as usize).ok_or_else(|| {
+            PipelineError::BindingMismatch {
+                shader: self.shader.clone(),
+                mismatches: vec![format!("group {group} is not declared by the shader")],
+            }
+        })?;
+
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{} group {group}", self.shader)),
+            layout,
+            entries,
+        }))
+    }
+}
+
+/// Binding type of the texture or sampler `variable` of type `ty`.
+///
+/// `sampling` lists the textures and samplers used together, as `(image, sampler)` pairs:
+/// float textures are only filterable when sampled with a non-comparison sampler,
+/// and samplers used with a depth texture cannot be filtering.
+fn handle_binding_type(
+    module: &naga::Module,
+    variable: naga::Handle<naga::GlobalVariable>,
+    ty: naga::Handle<naga::Type>,
+    sampling: &[(
+        naga::Handle<naga::GlobalVariable>,
+        naga::Handle<naga::GlobalVariable>,
+    )],
+) -> Option<wgpu::BindingType> {
+    // Types of the resources sampled together with `variable`.
+    let mut sampled_with = sampling.iter().filter_map(|&(image, sampler)| {
+        let other = if image == variable {
+            sampler
+        } else if sampler == variable {
+            image
+        } else {
+            return None;
+        };
+        Some(&module.types[module.global_variables[other].ty].inner)
+    });
+
+    match module.types[ty].inner {
+        naga::TypeInner::Sampler { comparison: true } => Some(wgpu::BindingType::Sampler(
+            wgpu::SamplerBindingType::Comparison,
+        )),
+        naga::TypeInner::Sampler { comparison: false } => {
+            let depth = sampled_with.any(|image| {
+                matches!(
+                    image,
+                    naga::TypeInner::Image {
+                        class: naga::ImageClass::Depth { .. },
+                        ..
+                    }
+                )
+            });
+            Some(wgpu::BindingType::Sampler(if depth {
+                wgpu::SamplerBindingType::NonFiltering
+            } else {
+                wgpu::SamplerBindingType::Filtering
+            }))
+        }
+        naga::TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => {
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                _ => return None,
+            };
+            Some(match class {
+                naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        naga::ScalarKind::Float => wgpu::TextureSampleType::Float {
+                            filterable: sampled_with.any(|sampler| {
+                                matches!(sampler, naga::TypeInner::Sampler { comparison: false })
+                            }),
+                        },
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        _ => return None,
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                    access: match (
+                        access.contains(naga::StorageAccess::LOAD),
+                        access.contains(naga::StorageAccess::STORE),
+                    ) {
+                        (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                        (true, false) => wgpu::StorageTextureAccess::ReadOnly,
+                        _ => wgpu::StorageTextureAccess::WriteOnly,
+                    },
+                    format: storage_format(format)?,
+                    view_dimension,
+                },
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Texture format of the most common storage texture formats.
+fn storage_format(format: naga::StorageFormat) -> Option<wgpu::TextureFormat> {
+    use naga::StorageFormat as Naga;
+    use wgpu::TextureFormat as Wgpu;
+    Some(match format {
+        Naga::R32Uint => Wgpu::R32Uint,
+        Naga::R32Sint => Wgpu::R32Sint,
+        Naga::R32Float => Wgpu::R32Float,
+        Naga::Rg32Uint => Wgpu::Rg32Uint,
+        Naga::Rg32Sint => Wgpu::Rg32Sint,
+        Naga::Rg32Float => Wgpu::Rg32Float,
+        Naga::Rgba8Unorm => Wgpu::Rgba8Unorm,
+        Naga::Rgba8Snorm => Wgpu::Rgba8Snorm,
+        Naga::Rgba8Uint => Wgpu::Rgba8Uint,
+        Naga::Rgba8Sint => Wgpu::Rgba8Sint,
+        Naga::Bgra8Unorm => Wgpu::Bgra8Unorm,
+        Naga::Rgba16Uint => Wgpu::Rgba16Uint,
+        Naga::Rgba16Sint => Wgpu::Rgba16Sint,
+        Naga::Rgba16Float => Wgpu::Rgba16Float,
+        Naga::Rgba32Uint => Wgpu::Rgba32Uint,
+        Naga::Rgba32Sint => Wgpu::Rgba32Sint,
+        Naga::Rgba32Float => Wgpu::Rgba32Float,
+        _ => return None,
+    })
+}
+
+/// Describe why `resource` cannot be bound to a binding of type `expected`, if it cannot.
+fn check_resource(
+    expected: &wgpu::BindingType,
+    resource: &wgpu::BindingResource,
+) -> Option<String> {
+    match (expected, resource) {
+        (
+            wgpu::BindingType::Buffer {
+                ty,
+                min_binding_size,
+                ..
+            },
+            wgpu::BindingResource::Buffer(binding),
+        ) => {
+            let (usage, kind) = match ty {
+                wgpu::BufferBindingType::Uniform => (wgpu::BufferUsages::UNIFORM, "uniform"),
+                wgpu::BufferBindingType::Storage { .. } => (wgpu::BufferUsages::STORAGE, "storage"),
+            };
+            if !binding.buffer.usage().contains(usage) {
+                return Some(format!(
+                    "is a {kind} buffer, but the buffer lacks BufferUsages::{}",
+                    kind.to_uppercase()
+                ));
+            }
+
+            let size = binding
+                .size
+                .map_or(binding.buffer.size() - binding.offset, |size| size.get());
+            match min_binding_size {
+                Some(min_size) if size < min_size.get() => Some(format!(
+                    "expects at least {min_size} bytes, got a buffer of {size} bytes"
+                )),
+                _ => None,
+            }
+        }
+        (wgpu::BindingType::Sampler(_), wgpu::BindingResource::Sampler(_))
+        | (wgpu::BindingType::Texture { .. }, wgpu::BindingResource::TextureView(_))
+        | (wgpu::BindingType::StorageTexture { .. }, wgpu::BindingResource::TextureView(_))
+        | (wgpu::BindingType::Buffer { .. }, wgpu::BindingResource::BufferArray(_))
+        | (wgpu::BindingType::Sampler(_), wgpu::BindingResource::SamplerArray(_))
+        | (wgpu::BindingType::Texture { .. }, wgpu::BindingResource::TextureViewArray(_)) => None,
+        (expected, _) => Some(format!(
+            "expects a {}, got a {}",
+            binding_type_name(expected),
+            resource_name(resource)
+        )),
+    }
+}
+
+/// Name of a binding type, for error messages.
+fn binding_type_name(ty: &wgpu::BindingType) -> &'static str {
+    match ty {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            ..
+        } => "uniform buffer",
+        wgpu::BindingType::Buffer { .. } => "storage buffer",
+        wgpu::BindingType::Sampler(_) => "sampler",
+        wgpu::BindingType::Texture { .. } => "texture",
+        wgpu::BindingType::StorageTexture { .. } => "storage texture",
+        wgpu::BindingType::AccelerationStructure => "acceleration structure",
+    }
+}
+
+/// Name of a bound resource, for error messages.
+fn resource_name(resource: &wgpu::BindingResource) -> &'static str {
+    match resource {
+        wgpu::BindingResource::Buffer(_) => "buffer",
+        wgpu::BindingResource::BufferArray(_) => "buffer array",
+        wgpu::BindingResource::Sampler(_) => "sampler",
+        wgpu::BindingResource::SamplerArray(_) => "sampler array",
+        wgpu::BindingResource::TextureView(_) => "texture view",
+        wgpu::BindingResource::TextureViewArray(_) => "texture view array",
+        _ => "resource",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo_pipelines::boids;
+    use crate::{ShaderBuilderForLibrary, ShaderDefines};
+
+    /// Reflect a wgsl source that does not import anything.
+    fn reflect_source(source: &str) -> ShaderBindings {
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::default(),
+        )
+        .validate(&module)
+        .unwrap();
+        ShaderBindings::reflect("test.wgsl", &module, &info).unwrap()
+    }
+
+    fn reflect(name: &str, defines: &ShaderDefines) -> ShaderBindings {
+        let shader = ShaderBuilderForLibrary::compose(name, defines).unwrap();
+        let (module, info) = shader.validate_with_info().unwrap();
+        ShaderBindings::reflect(name, &module, &info).unwrap()
+    }
+
+    #[test]
+    fn test_reflect_buffers() {
+        let bindings = reflect(
+            "demos/boids/compute.wgsl",
+            &boids::Pipeline::compute_shader_defines(),
+        );
+        assert_eq!(bindings.group_count(), 1);
+
+        let entries: Vec<_> = bindings.group(0).map(|binding| binding.entry).collect();
+        let buffer = |ty, size| wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: wgpu::BufferSize::new(size),
+        };
+        assert_eq!(
+            entries.iter().map(|entry| entry.ty).collect::<Vec<_>>(),
+            [
+                buffer(wgpu::BufferBindingType::Uniform, 32),
+                // A single particle for runtime sized arrays.
+                buffer(wgpu::BufferBindingType::Storage { read_only: true }, 16),
+                buffer(wgpu::BufferBindingType::Storage { read_only: false }, 16),
+            ]
+        );
+        assert!(entries
+            .iter()
+            .all(|entry| entry.visibility == wgpu::ShaderStages::COMPUTE));
+    }
+
+    #[test]
+    fn test_reflect_visibility() {
+        // Uniforms are only used by the vertex shader.
+        let bindings = reflect("demos/polygon/draw.wgsl", &ShaderDefines::new());
+        let uniforms = bindings.group(0).next().unwrap();
+        assert_eq!(uniforms.name, "uniforms");
+        assert_eq!(uniforms.entry.visibility, wgpu::ShaderStages::VERTEX);
+
+        // Particles are vertex buffers, nothing is bound.
+        let bindings = reflect("demos/boids/draw.wgsl", &ShaderDefines::new());
+        assert_eq!(bindings.group_count(), 0);
+    }
+
+    #[test]
+    fn test_check_bind_group() {
+        let bindings = reflect("demos/polygon/draw.wgsl", &ShaderDefines::new());
+
+        // Resources are created from a device, only missing bindings can be checked here.
+        let result = bindings.check_bind_group(0, &[]);
+        let Err(PipelineError::BindingMismatch { shader, mismatches }) = result else {
+            panic!("expected a binding mismatch, got {result:?}");
+        };
+        assert_eq!(shader, "demos/polygon/draw.wgsl");
+        assert_eq!(
+            mismatches,
+            ["binding 0 of group 0 (uniforms) is declared by the shader but not bound"]
+        );
+    }
+
+    #[test]
+    fn test_reflect_textures() {
+        let bindings = reflect_source(
+            "
+            @group(0) @binding(0) var depth: texture_depth_2d;
+            @group(0) @binding(1) var shadow: sampler_comparison;
+            @group(0) @binding(2) var data: texture_2d<f32>;
+            @group(0) @binding(3) var color: texture_2d<f32>;
+            @group(0) @binding(4) var linear: sampler;
+            @group(0) @binding(5) var nearest: sampler;
+            @group(0) @binding(6) var<uniform> unused: vec4<f32>;
+
+            @fragment
+            fn main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+                let uv = position.xy;
+                let lit = textureSampleCompare(depth, shadow, uv, 0.5);
+                let depth_value = textureSample(depth, nearest, uv);
+                let value = textureLoad(data, vec2<i32>(position.xy), 0);
+                let tint = textureSample(color, linear, uv);
+                return tint * value * lit * depth_value;
+            }
+            ",
+        );
+
+        let texture = |sample_type| wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        };
+        let float = |filterable| texture(wgpu::TextureSampleType::Float { filterable });
+        assert_eq!(
+            bindings
+                .group(0)
+                .map(|binding| binding.entry.ty)
+                .collect::<Vec<_>>(),
+            [
+                texture(wgpu::TextureSampleType::Depth),
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                // Only loaded, never sampled.
+                float(false),
+                float(true),
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                // Depth textures cannot be sampled with a filtering sampler.
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(16),
+                },
+            ]
+        );
+
+        // Unused resources are kept, so rust can still bind them.
+        let unused = bindings.group(0).last().unwrap();
+        assert_eq!(unused.name, "unused");
+        assert_eq!(unused.entry.visibility, wgpu::ShaderStages::FRAGMENT);
+    }
+}
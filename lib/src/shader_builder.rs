@@ -1,14 +1,306 @@
 use rust_embed::RustEmbed;
 
 use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
 
-use crate::pipeline::PipelineError;
+use crate::composed_shader::{device_capabilities, ComposedShader, ShaderLanguage, SourceLine};
+use crate::pipeline::{PipelineError, ShaderLocation};
+use crate::shader_bindings::ShaderBindings;
+use crate::wgsl_struct::load_rust_struct;
+
+/// Values defined when building a shader.
+/// Each define is used by `#ifdef` and `#ifndef`, and is injected
+/// in the shader as a `const` (or an `alias` for types) named after it.
+/// Glsl shaders get a `#define` instead.
+pub type ShaderDefines = HashMap<String, ShaderDefValue>;
+
+/// Value of a shader define.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShaderDefValue {
+    /// `false` is considered as not defined by `#ifdef`.
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+    /// Wgsl type name, such as `f32` or `vec3<f32>`.
+    Type(String),
+}
+
+/// Declarations of each module path, see [`find_import_paths`].
+pub type ModuleDeclarations = HashMap<String, Vec<ModuleDeclaration>>;
+
+/// `#define_import_path` line declaring a module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleDeclaration {
+    pub file: String,
+    /// 1-based line of the declaration.
+    pub line: usize,
+    /// Declaration line, for error reporting.
+    pub snippet: String,
+}
+
+/// Shader files declaring a module with `#define_import_path`.
+///
+/// Files are only listed and scanned on the first module import,
+/// so shaders without module imports do not load every file of the folder.
+pub struct ImportPaths<'a> {
+    /// Files that may declare a module.
+    files: Box<dyn Fn() -> Vec<String> + 'a>,
+    declarations: OnceCell<ModuleDeclarations>,
+}
+
+impl<'a> ImportPaths<'a> {
+    /// Modules declared by `files`, scanned when they are first resolved.
+    pub fn new(files: impl Fn() -> Vec<String> + 'a) -> Self {
+        Self {
+            files: Box::new(files),
+            declarations: OnceCell::new(),
+        }
+    }
+
+    /// Find the file declaring the module `path`, loading files with `load`.
+    /// Returns `None` if no file declares it.
+    ///
+    /// # Errors
+    /// - [`PipelineError::ShaderPreprocessError`] if several files declare the module.
+    pub fn resolve(
+        &self,
+        path: &str,
+        load: &dyn Fn(&str) -> Result<String, PipelineError>,
+    ) -> Result<Option<&ModuleDeclaration>, PipelineError> {
+        let declarations = self
+            .declarations
+            .get_or_init(|| find_import_paths((self.files)(), load));
+        match declarations.get(path).map(Vec::as_slice) {
+            None | Some([]) => Ok(None),
+            Some([declaration]) => Ok(Some(declaration)),
+            Some([first, duplicate, ..]) => Err(PipelineError::ShaderPreprocessError {
+                location: ShaderLocation {
+                    file: duplicate.file.clone(),
+                    line: duplicate.line,
+                    column: 1,
+                    length: duplicate.snippet.len(),
+                },
+                message: format!("module {path} is already declared in {}", first.file),
+                snippet: duplicate.snippet.clone(),
+            }),
+        }
+    }
+}
+
+impl Default for ImportPaths<'_> {
+    /// No module can be imported.
+    fn default() -> Self {
+        Self::new(Vec::new)
+    }
+}
+
+impl ShaderDefValue {
+    /// Whether `#ifdef` considers the define as set.
+    fn is_defined(&self) -> bool {
+        *self != Self::Bool(false)
+    }
+
+    /// Wgsl declaration of the define.
+    ///
+    /// # Errors
+    /// - [`PipelineError::InvalidDefine`] if the value cannot be written in wgsl, such as `NaN`.
+    fn declaration(&self, name: &str) -> Result<String, PipelineError> {
+        Ok(match self {
+            Self::Bool(value) => format!("const {name}: bool = {value};"),
+            // `2147483648i` is out of range before being negated.
+            Self::Int(i32::MIN) => format!("const {name}: i32 = (-2147483647i - 1i);"),
+            Self::Int(value) => format!("const {name}: i32 = {value}i;"),
+            Self::UInt(value) => format!("const {name}: u32 = {value}u;"),
+            // Debug keeps the decimal point, so `1.0` is not written as `1`.
+            Self::Float(value) => format!("const {name}: f32 = {};", Self::float(name, *value)?),
+            Self::Type(value) => format!("alias {name} = {value};"),
+        })
+    }
+
+    /// Glsl declaration of the define, `None` if it is not defined.
+    ///
+    /// # Errors
+    /// - [`PipelineError::InvalidDefine`] if the value cannot be written in glsl, such as `NaN`.
+    fn glsl_declaration(&self, name: &str) -> Result<Option<String>, PipelineError> {
+        let value = match self {
+            Self::Bool(false) => return Ok(None),
+            Self::Bool(true) => "1".to_owned(),
+            Self::Int(i32::MIN) => "(-2147483647 - 1)".to_owned(),
+            Self::Int(value) => value.to_string(),
+            Self::UInt(value) => format!("{value}u"),
+            Self::Float(value) => Self::float(name, *value)?,
+            Self::Type(value) => value.clone(),
+        };
+        Ok(Some(format!("#define {name} {value}")))
+    }
+
+    /// Float literal of a define, shaders have no literal for `NaN` and infinities.
+    fn float(name: &str, value: f32) -> Result<String, PipelineError> {
+        if !value.is_finite() {
+            return Err(PipelineError::InvalidDefine {
+                name: name.to_owned(),
+                message: format!("{value} is not a finite float"),
+            });
+        }
+        Ok(format!("{value:?}"))
+    }
+}
+
+impl From<bool> for ShaderDefValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i32> for ShaderDefValue {
+    fn from(value: i32) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<u32> for ShaderDefValue {
+    fn from(value: u32) -> Self {
+        Self::UInt(value)
+    }
+}
+
+impl From<f32> for ShaderDefValue {
+    fn from(value: f32) -> Self {
+        Self::Float(value)
+    }
+}
+
+/// Files included by the last successful build of each shader, by shader name.
+/// Filled by `ShaderBuilderFor::compose`, see [`shader_dependencies`].
+static SHADER_DEPENDENCIES: Mutex<BTreeMap<String, Vec<String>>> = Mutex::new(BTreeMap::new());
+
+/// Files included by the shaders built successfully since the last call to [`take_built_files`].
+static BUILT_FILES: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Files included by the shaders built successfully since the last call,
+/// so errors can be cleared only for the shaders that were rebuilt.
+pub fn take_built_files() -> BTreeSet<String> {
+    std::mem::take(&mut BUILT_FILES.lock().unwrap())
+}
+
+/// Files included by the last successful build of `name`, including itself.
+/// Returns `None` if the shader was not built yet or its last build failed.
+pub fn shader_dependencies(name: &str) -> Option<Vec<String>> {
+    let name = resolve_import("", name)?;
+    SHADER_DEPENDENCIES.lock().unwrap().get(&name).cloned()
+}
 
 /// Shaders used by this library for demo.
 #[derive(RustEmbed)]
 #[folder = "../shaders"]
 pub struct LibraryShaders;
 
+/// Folder of [`LibraryShaders`] on disk, watched by the runner unless `--shaders` overrides it.
+/// Absolute so the watcher does not depend on the working directory.
+pub const LIBRARY_SHADERS_FOLDER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../shaders");
+
+/// Shared shader library, imported with the `shared:` prefix.
+#[derive(RustEmbed)]
+#[folder = "../shared_shaders"]
+pub struct SharedShaders;
+
+/// Environment variable overriding the folder of [`LibraryShaders`] in native debug builds,
+/// set by the `--shaders` flag of the runner.
+/// Files are then read from this folder instead of the `RustEmbed` one.
+/// Unlike a static, the variable survives the library reloads.
+pub const SHADER_ROOT_VAR: &str = "SHADER_ROOT";
+
+/// `RustEmbed` folder a [`ShaderBuilderFor`] loads shaders from.
+pub trait ShaderFolder: RustEmbed {
+    /// Environment variable overriding the folder in native debug builds,
+    /// `None` to always read the `RustEmbed` one.
+    const ROOT_VAR: Option<&'static str> = None;
+}
+
+impl ShaderFolder for LibraryShaders {
+    const ROOT_VAR: Option<&'static str> = Some(SHADER_ROOT_VAR);
+}
+
+/// Folder overriding the `RustEmbed` one of `T`, see [`ShaderFolder::ROOT_VAR`].
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+fn override_folder<T: ShaderFolder>() -> Option<std::path::PathBuf> {
+    T::ROOT_VAR
+        .and_then(std::env::var_os)
+        .map(std::path::PathBuf::from)
+}
+
+/// Shader folder imported with a prefix, such as `#import "shared:math.wgsl"`.
+/// Listed in [`SHADER_ROOTS`](crate::SHADER_ROOTS), and embedded like the main folder.
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderRoot {
+    /// Prefix of the imports resolved in this folder, ending with `:`.
+    pub prefix: &'static str,
+    /// Folder on disk, watched by the runner in native debug builds.
+    pub folder: &'static str,
+    pub get: fn(&str) -> Option<rust_embed::EmbeddedFile>,
+    /// Files of the folder, relative to it.
+    pub files: fn() -> Vec<String>,
+}
+
+impl ShaderRoot {
+    pub const fn of<T: RustEmbed>(prefix: &'static str, folder: &'static str) -> Self {
+        Self {
+            prefix,
+            folder,
+            get: T::get,
+            files: embedded_files::<T>,
+        }
+    }
+}
+
+fn embedded_files<T: RustEmbed>() -> Vec<String> {
+    T::iter().map(String::from).collect()
+}
+
+/// Load a file of a [`ShaderRoot`], or `None` if `import` has no registered prefix.
+///
+/// # Errors
+/// - [`PipelineError::ShaderNotFound`] if the folder has no such file.
+pub fn load_prefixed(import: &str) -> Option<Result<Cow<'static, [u8]>, PipelineError>> {
+    let (root, path) = crate::SHADER_ROOTS
+        .iter()
+        .find_map(|root| Some((root, import.strip_prefix(root.prefix)?)))?;
+    Some(
+        (root.get)(path)
+            .map(|file| file.data)
+            .ok_or_else(|| PipelineError::ShaderNotFound {
+                file: import.to_owned(),
+            }),
+    )
+}
+
+/// Files of every [`ShaderRoot`], with their prefix.
+pub fn prefixed_files() -> Vec<String> {
+    crate::SHADER_ROOTS
+        .iter()
+        .flat_map(|root| {
+            (root.files)()
+                .into_iter()
+                .map(|file| format!("{}{file}", root.prefix))
+        })
+        .collect()
+}
+
+/// Decode a text shader file.
+///
+/// # Errors
+/// - [`PipelineError::InvalidUtf8`] if the file is not utf8.
+pub fn shader_text(name: &str, bytes: &[u8]) -> Result<String, PipelineError> {
+    std::str::from_utf8(bytes)
+        .map(str::to_owned)
+        .or(Err(PipelineError::InvalidUtf8 {
+            file: name.to_owned(),
+        }))
+}
+
 /// Shader helpers
 /// Will load from file in native debug mode to allow reloading at runtime
 /// and embed in binary in wasm/release mode.
@@ -16,30 +308,122 @@ pub struct LibraryShaders;
 // #[folder = "../shaders/"]
 pub struct ShaderBuilderFor<T>(pub T);
 
-impl<T: RustEmbed> ShaderBuilderFor<T> {
+impl<T: ShaderFolder> ShaderBuilderFor<T> {
     /// Load a shader file.
     /// Does not do any pre-processing here, but returns the raw content.
+    /// `rust:` imports are generated from the rust structs, see [`crate::wgsl_struct`],
+    /// and prefixed imports are loaded from their [`ShaderRoot`].
     pub fn load(name: &str) -> Result<String, PipelineError> {
-        // read file.
+        if let Some(declaration) = load_rust_struct(name) {
+            return declaration;
+        }
+        Self::get(name).and_then(|file| shader_text(name, &file))
+    }
+
+    /// Load a binary shader file, such as a precompiled SPIR-V.
+    pub fn load_binary(name: &str) -> Result<Vec<u8>, PipelineError> {
+        Self::get(name).map(Cow::into_owned)
+    }
+
+    /// Read a file of the folder, or of the [`ShaderRoot`] of its prefix.
+    fn get(name: &str) -> Result<Cow<'static, [u8]>, PipelineError> {
+        if let Some(file) = load_prefixed(name) {
+            return file;
+        }
+
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        if let Some(folder) = override_folder::<T>() {
+            return std::fs::read(folder.join(name))
+                .map(Cow::Owned)
+                .map_err(|_| PipelineError::ShaderNotFound {
+                    file: name.to_owned(),
+                });
+        }
+
         T::get(name)
-            // convert to PipelineError if file not found.
-            .ok_or(PipelineError::ShaderNotFound(format!(
-                "Could not load shader file: {name}"
-            )))
-            // Try parsing to utf8.
-            .and_then(|file| {
-                std::str::from_utf8(file.data.as_ref())
-                    .map(str::to_owned)
-                    .or(Err(PipelineError::ShaderNotFound(format!(
-                        "Shader file {name} is not a valid utf8."
-                    ))))
+            .map(|file| file.data)
+            .ok_or_else(|| PipelineError::ShaderNotFound {
+                file: name.to_owned(),
             })
     }
 
+    /// Files of the folder and of the [`ShaderRoot`]s.
+    fn files() -> Vec<String> {
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        let files = match override_folder::<T>() {
+            Some(folder) => folder_files(&folder),
+            None => embedded_files::<T>(),
+        };
+        #[cfg(not(all(debug_assertions, not(target_arch = "wasm32"))))]
+        let files = embedded_files::<T>();
+
+        files.into_iter().chain(prefixed_files()).collect()
+    }
+
     /// Build a shader file by importing all its dependencies.
-    /// todo: Add #ifdef #else #endif #ifndef support.
     pub fn build(name: &str) -> Result<String, PipelineError> {
-        Self::build_with_seen(name, &mut vec![])
+        Self::build_with_defines(name, &ShaderDefines::new())
+    }
+
+    /// Build a shader file by importing all its dependencies,
+    /// resolving `#ifdef` / `#ifndef` / `#else` / `#endif` blocks with the given defines.
+    /// The defines are also declared as wgsl constants at the top of the shader,
+    /// so rust stays the single source of truth for values shared with the gpu.
+    pub fn build_with_defines(
+        name: &str,
+        defines: &ShaderDefines,
+    ) -> Result<String, PipelineError> {
+        Ok(Self::compose(name, defines)?.code)
+    }
+
+    /// Build a shader file like `build_with_defines`,
+    /// keeping track of the original file and line of each line of the output.
+    /// The files it includes are recorded for [`shader_dependencies`].
+    /// The language is picked from the extension, see [`ShaderLanguage`]:
+    /// SPIR-V files are loaded as is, without preprocessing.
+    pub fn compose(name: &str, defines: &ShaderDefines) -> Result<ComposedShader, PipelineError> {
+        let name = resolve_import("", name).unwrap_or_else(|| name.to_owned());
+        let shader = match ShaderLanguage::from_file(&name) {
+            ShaderLanguage::SpirV => {
+                Self::load_binary(&name).map(|binary| ComposedShader::from_spirv(&name, binary))
+            }
+            _ => compose_with(&Self::load, &Self::import_paths(), &name, defines),
+        };
+
+        let mut dependencies = SHADER_DEPENDENCIES.lock().unwrap();
+        match &shader {
+            Ok(shader) => {
+                BUILT_FILES
+                    .lock()
+                    .unwrap()
+                    .extend(shader.imports.keys().cloned());
+                dependencies.insert(name, shader.imports.keys().cloned().collect());
+            }
+            // The failed build may depend on files that do not exist yet,
+            // forget its dependencies so any change rebuilds it.
+            Err(_) => {
+                dependencies.remove(&name);
+            }
+        }
+
+        shader
+    }
+
+    /// Files of the folder and of the [`ShaderRoot`]s
+    /// declaring a module with `#define_import_path`, scanned on the first module import.
+    pub fn import_paths() -> ImportPaths<'static> {
+        // The function pointer does not borrow `T`.
+        let files: fn() -> Vec<String> = Self::files;
+        ImportPaths::new(files)
+    }
+
+    /// Build a shader file and validate it with naga, without requiring a gpu.
+    /// Useful to check shaders in tests or tools.
+    /// Only naga's default capabilities are allowed, see [`ComposedShader::capabilities`].
+    pub fn validate(name: &str, defines: &ShaderDefines) -> Result<naga::Module, PipelineError> {
+        Self::compose(name, defines)?
+            .validate()
+            .map_err(PipelineError::ShaderParseError)
     }
 
     /// Create a shader module from a shader file.
@@ -47,70 +431,650 @@ impl<T: RustEmbed> ShaderBuilderFor<T> {
         device: &wgpu::Device,
         name: &str,
     ) -> Result<wgpu::ShaderModule, PipelineError> {
-        let shader = ShaderBuilderFor::<T>::build(name)?;
+        Self::create_module_with_defines(device, name, &ShaderDefines::new())
+    }
+
+    /// Create a shader module from a shader file, using the given defines.
+    pub fn create_module_with_defines(
+        device: &wgpu::Device,
+        name: &str,
+        defines: &ShaderDefines,
+    ) -> Result<wgpu::ShaderModule, PipelineError> {
+        let shader = Self::compose_for_device(device, name, defines)?;
+        Self::create_composed_module(device, name, &shader)
+    }
+
+    /// Create a shader module from a shader file, using the given defines,
+    /// along with the resources it binds, reflected with naga.
+    /// Use them to create the bind group layouts and pipeline layout
+    /// instead of mirroring the shader declarations in rust.
+    pub fn create_module_with_bindings(
+        device: &wgpu::Device,
+        name: &str,
+        defines: &ShaderDefines,
+    ) -> Result<(wgpu::ShaderModule, ShaderBindings), PipelineError> {
+        let shader = Self::compose_for_device(device, name, defines)?;
+        let (module, info) = shader
+            .validate_with_info()
+            .map_err(PipelineError::ShaderParseError)?;
+        let bindings = ShaderBindings::reflect(name, &module, &info)?;
+        Ok((
+            Self::create_composed_module(device, name, &shader)?,
+            bindings,
+        ))
+    }
+
+    /// Build a shader file like `compose`, validated with the capabilities of `device`
+    /// rather than the offline ones.
+    fn compose_for_device(
+        device: &wgpu::Device,
+        name: &str,
+        defines: &ShaderDefines,
+    ) -> Result<ComposedShader, PipelineError> {
+        let mut shader = Self::compose(name, defines)?;
+        shader.capabilities = device_capabilities(device.features());
+        Ok(shader)
+    }
 
+    /// Create a shader module from a composed shader.
+    fn create_composed_module(
+        device: &wgpu::Device,
+        name: &str,
+        shader: &ComposedShader,
+    ) -> Result<wgpu::ShaderModule, PipelineError> {
         // device.create_shader_module panics if the shader is malformed
         // only check this on native debug builds.
         #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
         device.push_error_scope(wgpu::ErrorFilter::Validation);
 
+        // wgpu panics on malformed SPIR-V, even in an error scope.
+        if shader.language == ShaderLanguage::SpirV {
+            shader.validate().map_err(PipelineError::ShaderParseError)?;
+        }
+
         let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some(name),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(shader.as_str())),
+            source: shader.source(),
         });
 
         // device.create_shader_module panics if the shader is malformed
         // only check this on native debug builds.
         #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
         if let Some(error) = pollster::block_on(device.pop_error_scope()) {
-            log::error!("{name}: {}", error);
-            // redundant, naga already logs the error.
-            return Err(PipelineError::ShaderParseError(format!("{error}")));
+            // wgpu reports lines of the composed shader, which are meaningless once files
+            // are imported. Ask naga again to get locations in the original files.
+            let error = match shader.diagnose() {
+                Some(diagnostic) => PipelineError::ShaderParseError(diagnostic),
+                None => PipelineError::DeviceValidation {
+                    label: name.to_owned(),
+                    message: format!("{error}"),
+                },
+            };
+            log::error!("{name}: {error}");
+            return Err(error);
         }
 
         Ok(module)
     }
+}
+
+/// Build a shader file with files provided by `load`, like `ShaderBuilderFor::compose`.
+/// `ShaderBuilderFor` loads files from its `RustEmbed` folder,
+/// this allows tools to load them from anywhere.
+/// `import_paths` resolves module imports, see [`find_import_paths`].
+///
+/// Glsl files only support `#include "file"`: other directives are left to naga's glsl preprocessor,
+/// and the defines are written as `#define` after `#version`.
+pub fn compose_with(
+    load: &dyn Fn(&str) -> Result<String, PipelineError>,
+    import_paths: &ImportPaths<'_>,
+    name: &str,
+    defines: &ShaderDefines,
+) -> Result<ComposedShader, PipelineError> {
+    // Sort the defines so the output does not depend on the hashmap order.
+    let mut names: Vec<&String> = defines.keys().collect();
+    names.sort();
 
+    let language = ShaderLanguage::from_file(name);
+    let glsl = matches!(language, ShaderLanguage::Glsl(_));
+    let mut output = ComposedShader {
+        language,
+        ..Default::default()
+    };
+    let mut glsl_defines = vec![];
+    for define in names {
+        if glsl {
+            glsl_defines.extend(defines[define].glsl_declaration(define)?);
+        } else {
+            output.push_line(&defines[define].declaration(define)?, None);
+        }
+    }
+    // Normalize the name so `seen` recognizes the file if it is imported again.
+    let name = resolve_import("", name).ok_or_else(|| PipelineError::ShaderNotFound {
+        file: name.to_owned(),
+    })?;
+
+    let mut preprocessor = Preprocessor {
+        load,
+        import_paths,
+        defines,
+        glsl,
+        glsl_defines,
+        seen: vec![],
+        stack: vec![],
+        output,
+    };
+    preprocessor.include(&name)?;
+    preprocessor.flush_glsl_defines();
+    Ok(preprocessor.output)
+}
+
+/// State of the preprocessor while building a shader file.
+struct Preprocessor<'a> {
+    /// Load the content of a shader file.
+    load: &'a dyn Fn(&str) -> Result<String, PipelineError>,
+    import_paths: &'a ImportPaths<'a>,
+    defines: &'a ShaderDefines,
+    /// Whether the shader is written in glsl, see [`compose_with`].
+    glsl: bool,
+    /// Glsl `#define`s not written yet, waiting for `#version`.
+    glsl_defines: Vec<String>,
+    /// Files already included.
+    seen: Vec<String>,
+    /// Files currently being included, from the root file to the current one.
+    stack: Vec<String>,
+    output: ComposedShader,
+}
+
+impl Preprocessor<'_> {
     /// Build a shader file by importing all its dependencies.
     /// We use seen to make sure we do not import the same file twice.
     /// Order of import does not matter in wgsl, as it does not in rust
     /// so we don't need to sort the imports depending on their dependencies.
     /// However we cannot define the same symbol twice, so we need to make sure
     /// we do not import the same file twice.
-    fn build_with_seen(name: &str, seen: &mut Vec<String>) -> Result<String, PipelineError> {
-        // File was already included, return empty string.
+    /// A file importing one of the files in `stack` is an import cycle.
+    fn include(&mut self, name: &str) -> Result<(), PipelineError> {
+        // File is currently being included: import cycle.
+        if let Some(position) = self.stack.iter().position(|file| file == name) {
+            let mut chain = self.stack[position..].to_vec();
+            chain.push(name.to_owned());
+            return Err(PipelineError::ImportCycle { chain });
+        }
+
+        // File was already included, nothing to add.
         let owned_name = name.to_owned();
-        if seen.contains(&owned_name) {
-            return Ok("".to_owned());
+        if self.seen.contains(&owned_name) {
+            return Ok(());
         }
-        seen.push(owned_name);
+        self.seen.push(owned_name.clone());
+        self.stack.push(owned_name.clone());
+        self.output.imports.insert(owned_name, vec![]);
+
+        let source = (self.load)(name)?;
+        let mut blocks: Vec<ConditionalBlock> = vec![];
+        // Prefixes of the modules imported without items, such as `noise::` for `#import my::noise`.
+        let mut qualifiers: Vec<String> = vec![];
 
-        Self::load(name)?
-            .lines()
-            .map(|line| {
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let origin = || {
+                Some(SourceLine {
+                    file: name.to_owned(),
+                    line: line_number,
+                })
+            };
+            let location = || ShaderLocation {
+                file: name.to_owned(),
+                line: line_number,
+                column: 1,
+                length: line.len(),
+            };
+            let error = |message: String| PipelineError::ShaderPreprocessError {
+                location: location(),
+                message,
+                snippet: line.to_owned(),
+            };
+            let active = blocks.last().is_none_or(|block| block.active);
+
+            let trimmed = line.trim_start();
+            if self.glsl {
+                self.include_glsl_line(name, line, line_number)?;
+                continue;
+            }
+            if !trimmed.starts_with('#') {
+                if active {
+                    self.output
+                        .push_line(&strip_qualifiers(line, &qualifiers), origin());
+                }
+                continue;
+            }
+
+            let mut tokens = trimmed.split_whitespace();
+            let directive = tokens.next().unwrap_or_default();
+            match directive {
+                "#ifdef" | "#ifndef" => {
+                    let define = tokens
+                        .next()
+                        .ok_or_else(|| error(format!("expected {directive} NAME")))?;
+                    if tokens.next().is_some() {
+                        return Err(error(format!(
+                            "unexpected tokens after {directive} {define}"
+                        )));
+                    }
+                    let defined = self
+                        .defines
+                        .get(define)
+                        .is_some_and(ShaderDefValue::is_defined);
+                    let condition = if directive == "#ifdef" {
+                        defined
+                    } else {
+                        !defined
+                    };
+                    blocks.push(ConditionalBlock {
+                        parent_active: active,
+                        active: active && condition,
+                        in_else: false,
+                        line: line_number,
+                    });
+                }
+                "#else" => {
+                    let block = blocks
+                        .last_mut()
+                        .ok_or_else(|| error("#else without matching #ifdef".to_owned()))?;
+                    if block.in_else {
+                        return Err(error(format!(
+                            "duplicate #else for the block opened at line {}",
+                            block.line
+                        )));
+                    }
+                    block.in_else = true;
+                    // Only the branch that was not taken becomes active.
+                    block.active = block.parent_active && !block.active;
+                }
+                "#endif" => {
+                    blocks
+                        .pop()
+                        .ok_or_else(|| error("#endif without matching #ifdef".to_owned()))?;
+                }
                 // example of valid import: #import "common.wgsl"
                 // note: this follow the bevy preprocessor syntax.
                 // wgsl-analyzer is also based on the bevy preprocessor.
                 // but does not support #import "file" as of August 2023.
-                if line.starts_with("#import") {
-                    let include = line
-                        .split('"')
-                        .nth(1)
-                        .expect("Invalid import syntax: expected #import \"file\"");
-                    let include_content = Self::build_with_seen(include, seen)?;
-                    // We keep the import commented for debugging purposes.
-                    Ok(format!("//{line}\n {include_content}"))
-                } else {
-                    Ok(line.to_owned() + "\n")
+                "#import" => {
+                    if !active {
+                        continue;
+                    }
+                    let import =
+                        parse_import(trimmed).ok_or_else(|| PipelineError::InvalidImport {
+                            location: location(),
+                            line: line.to_owned(),
+                        })?;
+                    let include = match import {
+                        Import::File(include) => {
+                            resolve_import(name, include).ok_or_else(|| {
+                                error(format!("import {include} is outside of the shader folder"))
+                            })?
+                        }
+                        Import::Module { path, items } => {
+                            let (file, path) = self.resolve_module(path, items.is_empty())?
+                                .ok_or_else(|| {
+                                    error(format!(
+                                        "unknown module {path}, no shader declares it with #define_import_path"
+                                    ))
+                                })?;
+                            // Module items can be used qualified by the module name or its full path.
+                            if items.is_empty() {
+                                let module = path.rsplit("::").next().unwrap_or(path);
+                                qualifiers.push(format!("{path}::"));
+                                qualifiers.push(format!("{module}::"));
+                            }
+                            file
+                        }
+                    };
+                    self.import_file(name, include, line, line_number)?;
+                    continue;
                 }
-            })
-            .collect::<Result<String, PipelineError>>()
+                // Modules are found by `find_import_paths`, only check the syntax here.
+                "#define_import_path" => {
+                    if tokens.next().is_none_or(|path| !is_module_path(path))
+                        || tokens.next().is_some()
+                    {
+                        return Err(error(
+                            "expected #define_import_path path::to::module".to_owned(),
+                        ));
+                    }
+                }
+                _ => return Err(error(format!("unknown preprocessor directive {directive}"))),
+            }
+
+            // We keep the directives commented for debugging purposes.
+            self.output.push_line(&format!("//{line}"), origin());
+        }
+
+        if let Some(block) = blocks.last() {
+            return Err(PipelineError::ShaderPreprocessError {
+                location: ShaderLocation {
+                    file: name.to_owned(),
+                    line: block.line,
+                    column: 1,
+                    length: 0,
+                },
+                message: "unterminated conditional block, expected #endif".to_owned(),
+                snippet: source
+                    .lines()
+                    .nth(block.line - 1)
+                    .unwrap_or_default()
+                    .to_owned(),
+            });
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Include `include`, imported by `name` at `line`.
+    fn import_file(
+        &mut self,
+        name: &str,
+        include: String,
+        line: &str,
+        line_number: usize,
+    ) -> Result<(), PipelineError> {
+        // We keep the import commented for debugging purposes.
+        self.output.push_line(
+            &format!("//{line}"),
+            Some(SourceLine {
+                file: name.to_owned(),
+                line: line_number,
+            }),
+        );
+        self.output
+            .imports
+            .entry(name.to_owned())
+            .or_default()
+            .push(include.clone());
+        self.include(&include)
+    }
+
+    /// Handle a line of a glsl file: `#include` is resolved,
+    /// other lines are kept for naga's glsl preprocessor.
+    fn include_glsl_line(
+        &mut self,
+        name: &str,
+        line: &str,
+        line_number: usize,
+    ) -> Result<(), PipelineError> {
+        let origin = Some(SourceLine {
+            file: name.to_owned(),
+            line: line_number,
+        });
+        let trimmed = line.trim_start();
+
+        // Defines must follow `#version`, which must come before any code.
+        let is_comment = ["//", "/*", "*"]
+            .iter()
+            .any(|prefix| trimmed.starts_with(prefix));
+        if !trimmed.is_empty() && !is_comment {
+            if trimmed.starts_with("#version") {
+                self.output.push_line(line, origin);
+                self.flush_glsl_defines();
+                return Ok(());
+            }
+            self.flush_glsl_defines();
+        }
+
+        if !trimmed.starts_with("#include") {
+            self.output.push_line(line, origin);
+            return Ok(());
+        }
+
+        let location = ShaderLocation {
+            file: name.to_owned(),
+            line: line_number,
+            column: 1,
+            length: line.len(),
+        };
+        let include = parse_include(trimmed).ok_or_else(|| PipelineError::InvalidImport {
+            location: location.clone(),
+            line: line.to_owned(),
+        })?;
+        let include =
+            resolve_import(name, include).ok_or_else(|| PipelineError::ShaderPreprocessError {
+                location,
+                message: format!("include {include} is outside of the shader folder"),
+                snippet: line.to_owned(),
+            })?;
+        self.import_file(name, include, line, line_number)
+    }
+
+    /// Write the glsl defines that were not written yet.
+    fn flush_glsl_defines(&mut self) {
+        for define in std::mem::take(&mut self.glsl_defines) {
+            self.output.push_line(&define, None);
+        }
     }
+
+    /// Find the file declaring the module `path` and the declared path.
+    /// Like bevy, `#import my::noise::fbm` imports `fbm` from `my::noise`
+    /// if `my::noise::fbm` is not a module itself, unless `allow_item` is false.
+    /// Returns `None` if no file declares it.
+    fn resolve_module<'p>(
+        &self,
+        path: &'p str,
+        allow_item: bool,
+    ) -> Result<Option<(String, &'p str)>, PipelineError> {
+        if let Some(declaration) = self.import_paths.resolve(path, self.load)? {
+            return Ok(Some((declaration.file.clone(), path)));
+        }
+        let Some((module, _item)) = path.rsplit_once("::").filter(|_| allow_item) else {
+            return Ok(None);
+        };
+        let declaration = self.import_paths.resolve(module, self.load)?;
+        Ok(declaration.map(|declaration| (declaration.file.clone(), module)))
+    }
+}
+
+/// Find the wgsl files declaring a module with `#define_import_path path::to::module`.
+/// Only the first declaration of each file is considered,
+/// files that cannot be loaded are skipped: they only fail the shaders importing them.
+/// Modules declared several times are only reported when imported, see [`ImportPaths::resolve`].
+pub fn find_import_paths(
+    files: impl IntoIterator<Item = String>,
+    load: &dyn Fn(&str) -> Result<String, PipelineError>,
+) -> ModuleDeclarations {
+    let mut declarations = ModuleDeclarations::new();
+    for file in files {
+        if !file.ends_with(".wgsl") {
+            continue;
+        }
+        let Ok(source) = load(&file) else {
+            continue;
+        };
+        let declaration = source.lines().enumerate().find_map(|(index, line)| {
+            let rest = line.trim_start().strip_prefix("#define_import_path")?;
+            // `#define_import_pathfoo` is not the directive.
+            if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            Some((index + 1, line, rest.split_whitespace().next()?))
+        });
+        let Some((line, snippet, path)) = declaration else {
+            continue;
+        };
+
+        declarations
+            .entry(path.to_owned())
+            .or_default()
+            .push(ModuleDeclaration {
+                file,
+                line,
+                snippet: snippet.to_owned(),
+            });
+    }
+    declarations
+}
+
+/// Import of an `#import` line.
+#[derive(Debug, PartialEq)]
+enum Import<'a> {
+    /// `#import "path/to/file.wgsl"`
+    File(&'a str),
+    /// `#import my::noise` or `#import my::noise::{fbm, hash}`, items being empty for the former.
+    Module { path: &'a str, items: Vec<&'a str> },
+}
+
+/// Parse a glsl `#include "file"` line, trailing comments are allowed.
+fn parse_include(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("#include")?;
+    let rest = rest.split_once("//").map_or(rest, |(rest, _)| rest).trim();
+    let include = rest.strip_prefix('"')?.strip_suffix('"')?;
+    (!include.is_empty() && !include.contains('"')).then_some(include)
+}
+
+/// Parse an `#import "file"`, `#import my::noise` or `#import my::noise::{fbm, hash}` line.
+/// Returns `None` if the line is malformed, for instance while it is being typed.
+/// Trailing comments are allowed.
+fn parse_import(line: &str) -> Option<Import<'_>> {
+    let rest = line.strip_prefix("#import")?;
+    // Remove the trailing comment, no path contains `//`.
+    let rest = rest.split_once("//").map_or(rest, |(rest, _)| rest).trim();
+
+    if let Some(rest) = rest.strip_prefix('"') {
+        let include = rest.strip_suffix('"')?;
+        return (!include.is_empty() && !include.contains('"')).then_some(Import::File(include));
+    }
+
+    let (path, items) = match rest.split_once("::{") {
+        Some((path, items)) => {
+            let items: Vec<&str> = items.strip_suffix('}')?.split(',').map(str::trim).collect();
+            // Allow a trailing comma.
+            let items = match items.split_last() {
+                Some((&"", items)) if !items.is_empty() => items.to_vec(),
+                _ => items,
+            };
+            items.iter().all(|item| is_identifier(item)).then_some(())?;
+            (path, items)
+        }
+        None => (rest, vec![]),
+    };
+    is_module_path(path).then_some(Import::Module { path, items })
+}
+
+/// Whether `name` is a valid wgsl identifier.
+fn is_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether `path` is a module path, such as `my::noise`.
+fn is_module_path(path: &str) -> bool {
+    path.split("::").all(is_identifier)
+}
+
+/// Remove the module qualifiers of a line, turning `noise::fbm(uv)` into `fbm(uv)`,
+/// since the items of imported modules are declared in the global scope.
+fn strip_qualifiers<'l>(line: &'l str, qualifiers: &[String]) -> Cow<'l, str> {
+    if qualifiers.is_empty() || !line.contains("::") {
+        return Cow::Borrowed(line);
+    }
+
+    let mut line = line.to_owned();
+    for qualifier in qualifiers {
+        let mut start = 0;
+        while let Some(position) = line[start..].find(qualifier.as_str()) {
+            let position = start + position;
+            // Only strip whole qualifiers: `noise::` but not `my_noise::` or `my::noise::`.
+            let preceded = line[..position]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == ':');
+            if preceded {
+                start = position + qualifier.len();
+            } else {
+                line.replace_range(position..position + qualifier.len(), "");
+                start = position;
+            }
+        }
+    }
+    Cow::Owned(line)
+}
+
+/// Files of a folder on disk, relative to it.
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+fn folder_files(root: &std::path::Path) -> Vec<String> {
+    let mut files = vec![];
+    let mut folders = vec![root.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let Ok(entries) = std::fs::read_dir(&folder) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.is_dir() {
+                folders.push(path);
+            } else if let Ok(name) = path.strip_prefix(root) {
+                files.push(name.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files
+}
+
+/// Split the `prefix:` of an import, empty for files of the main shader root.
+fn split_prefix(import: &str) -> (&str, &str) {
+    import
+        .find(':')
+        .map_or(("", import), |index| import.split_at(index + 1))
+}
+
+/// Resolve an import to a normalized path from the shader root.
+/// `./file` and `../file` are relative to the importing file, keeping its prefix,
+/// other paths (optionally starting with `/`) are relative to the root of their prefix,
+/// the main shader root if they have none.
+/// Returns `None` if the path escapes the shader root.
+fn resolve_import(importer: &str, include: &str) -> Option<String> {
+    let (mut prefix, include) = split_prefix(include);
+    let mut parts: Vec<&str> = vec![];
+    if include.starts_with("./") || include.starts_with("../") {
+        let (importer_prefix, importer) = split_prefix(importer);
+        prefix = importer_prefix;
+        parts.extend(importer.split('/'));
+        // Remove the importing file name to keep its folder.
+        parts.pop();
+    }
+
+    for part in include.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            _ => parts.push(part),
+        }
+    }
+
+    Some(format!("{prefix}{}", parts.join("/")))
+}
+
+/// State of an `#ifdef` / `#ifndef` block while preprocessing a file.
+struct ConditionalBlock {
+    /// Whether the enclosing block is emitting lines.
+    parent_active: bool,
+    /// Whether lines in the current branch are emitted.
+    active: bool,
+    /// Whether `#else` was already encountered.
+    in_else: bool,
+    /// Line of the opening directive, for error reporting.
+    line: usize,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::demo_pipelines::{boids, raymarching};
+    use crate::pipeline::ShaderChanges;
     use std::borrow::Cow;
 
     #[test]
@@ -157,4 +1121,523 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_validate_library_shaders() {
+        // Variants built by the demo pipelines.
+        let variants = |file: &str| match file {
+            "demos/boids/compute.wgsl" => vec![boids::Pipeline::compute_shader_defines()],
+            "demos/raymarching/draw.wgsl" => vec![
+                raymarching::Pipeline::shader_defines(false),
+                raymarching::Pipeline::shader_defines(true),
+            ],
+            _ => vec![ShaderDefines::new()],
+        };
+
+        let mut errors = vec![];
+        for file in LibraryShaders::iter() {
+            // These shaders are broken on purpose.
+            if file.starts_with("test_preprocessor/errors/") {
+                continue;
+            }
+
+            // Only check entry points: other files are only valid once imported.
+            // Glsl and SPIR-V files have a single stage given by their extension.
+            if ShaderLanguage::from_file(&file) == ShaderLanguage::Wgsl {
+                let source = ShaderBuilderFor::<LibraryShaders>::load(&file).unwrap();
+                if !["@vertex", "@fragment", "@compute"]
+                    .iter()
+                    .any(|stage| source.contains(stage))
+                {
+                    continue;
+                }
+            }
+
+            for defines in variants(&file) {
+                if let Err(error) = ShaderBuilderFor::<LibraryShaders>::validate(&file, &defines) {
+                    errors.push(format!("{file} ({defines:?}): {error}"));
+                }
+            }
+        }
+
+        assert!(errors.is_empty(), "{}", errors.join("\n"));
+    }
+
+    #[test]
+    fn test_conditional_blocks() -> Result<(), PipelineError> {
+        let build = |defines: &[&str]| {
+            let defines = defines
+                .iter()
+                .map(|&define| (define.to_owned(), ShaderDefValue::Bool(true)))
+                .collect();
+            ShaderBuilderFor::<LibraryShaders>::build_with_defines(
+                "test_preprocessor/conditional.wgsl",
+                &defines,
+            )
+        };
+
+        let shader = build(&[])?;
+        assert!(shader.contains("outer_undefined"));
+        assert!(!shader.contains("outer_defined"));
+        assert!(!shader.contains("inner_"));
+
+        let shader = build(&["OUTER"])?;
+        assert!(shader.contains("outer_defined"));
+        assert!(shader.contains("inner_undefined"));
+        assert!(!shader.contains("inner_defined"));
+
+        let shader = build(&["OUTER", "INNER"])?;
+        assert!(shader.contains("inner_defined"));
+        assert!(!shader.contains("inner_undefined"));
+        assert!(!shader.contains("outer_undefined"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_defines() -> Result<(), PipelineError> {
+        let defines = ShaderDefines::from([
+            ("OUTER".to_owned(), ShaderDefValue::Bool(false)),
+            ("COUNT".to_owned(), 64u32.into()),
+            ("SCALE".to_owned(), 1.0f32.into()),
+            ("Real".to_owned(), ShaderDefValue::Type("f32".to_owned())),
+        ]);
+        let shader = ShaderBuilderFor::<LibraryShaders>::build_with_defines(
+            "test_preprocessor/conditional.wgsl",
+            &defines,
+        )?;
+
+        // false booleans are not defined for #ifdef, but still declared.
+        assert!(shader.contains("outer_undefined"));
+        assert!(shader.contains("const OUTER: bool = false;"));
+        assert!(shader.contains("const COUNT: u32 = 64u;"));
+        assert!(shader.contains("const SCALE: f32 = 1.0;"));
+        assert!(shader.contains("alias Real = f32;"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_define_limits() -> Result<(), PipelineError> {
+        let defines = ShaderDefines::from([
+            ("MIN".to_owned(), i32::MIN.into()),
+            ("MAX".to_owned(), i32::MAX.into()),
+            ("LARGE".to_owned(), f32::MAX.into()),
+        ]);
+        let shader = ShaderBuilderFor::<LibraryShaders>::compose(
+            "test_preprocessor/conditional.wgsl",
+            &defines,
+        )?;
+        assert!(shader
+            .code
+            .contains("const MIN: i32 = (-2147483647i - 1i);"));
+        assert!(shader.code.contains("const MAX: i32 = 2147483647i;"));
+        shader.validate().map_err(PipelineError::ShaderParseError)?;
+
+        for value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let defines = ShaderDefines::from([("SCALE".to_owned(), value.into())]);
+            for file in [
+                "test_preprocessor/conditional.wgsl",
+                "test_glsl/particles.comp",
+            ] {
+                let result = ShaderBuilderFor::<LibraryShaders>::build_with_defines(file, &defines);
+                assert!(
+                    matches!(&result, Err(PipelineError::InvalidDefine { name, .. }) if name == "SCALE"),
+                    "{file} with {value}: {result:?}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_map() -> Result<(), PipelineError> {
+        let shader = ShaderBuilderFor::<LibraryShaders>::compose(
+            "test_preprocessor/errors/invalid.wgsl",
+            &ShaderDefines::new(),
+        )?;
+
+        // naga reports the error in the imported file, not in the composed shader.
+        let diagnostic = shader.diagnose().expect("shader should be invalid");
+        let location = diagnostic
+            .location()
+            .expect("naga should report a location");
+        assert_eq!(
+            location.file,
+            "test_preprocessor/errors/invalid_import.wgsl"
+        );
+        assert_eq!((location.line, location.column), (3, 31));
+        assert_eq!(
+            diagnostic.labels[0].snippet.trim(),
+            "return uniforms.elapsed * unknown_scale;"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_glsl_include() -> Result<(), PipelineError> {
+        let defines = ShaderDefines::from([("PARTICLES_PER_GROUP".to_owned(), 32.into())]);
+        let shader =
+            ShaderBuilderFor::<LibraryShaders>::compose("test_glsl/particles.comp", &defines)?;
+        assert_eq!(
+            shader.language,
+            ShaderLanguage::Glsl(naga::ShaderStage::Compute)
+        );
+
+        // Defines follow #version, the include is replaced by the file.
+        let lines: Vec<_> = shader.code.lines().collect();
+        assert_eq!(
+            lines[..2],
+            ["#version 450", "#define PARTICLES_PER_GROUP 32"]
+        );
+        assert!(shader.code.contains("vec2 wrap(vec2 position)"));
+        assert_eq!(
+            shader.imports["test_glsl/particles.comp"],
+            ["test_glsl/common.glsl"]
+        );
+
+        let (module, info) = shader
+            .validate_with_info()
+            .map_err(PipelineError::ShaderParseError)?;
+        assert_eq!(module.entry_points[0].workgroup_size, [32, 1, 1]);
+        let bindings = ShaderBindings::reflect("test_glsl/particles.comp", &module, &info)?;
+        assert_eq!(bindings.group(0).count(), 2);
+
+        assert_eq!(
+            parse_include("#include \"common.glsl\" // comment"),
+            Some("common.glsl")
+        );
+        assert_eq!(parse_include("#include <common.glsl>"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_glsl_errors() -> Result<(), PipelineError> {
+        let load = |name: &str| match name {
+            "broken.frag" => Ok("#version 450\n#include \"lib.glsl\"\nvoid main() {}\n".to_owned()),
+            "lib.glsl" => Ok("float scale() {\n    return unknown;\n}\n".to_owned()),
+            _ => ShaderBuilderFor::<LibraryShaders>::load(name),
+        };
+        let shader = compose_with(
+            &load,
+            &ImportPaths::default(),
+            "broken.frag",
+            &ShaderDefines::new(),
+        )?;
+
+        // Errors point to the included file.
+        let diagnostic = shader.diagnose().expect("shader should be invalid");
+        let location = diagnostic
+            .location()
+            .expect("naga should report a location");
+        assert_eq!((location.file.as_str(), location.line), ("lib.glsl", 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spirv_shader() -> Result<(), PipelineError> {
+        // Compile a glsl shader to SPIR-V rather than storing a binary in the repository.
+        let module = ShaderBuilderFor::<LibraryShaders>::validate(
+            "test_glsl/gradient.frag",
+            &ShaderDefines::new(),
+        )?;
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::default(),
+        )
+        .validate(&module)
+        .unwrap();
+        let words = naga::back::spv::write_vec(&module, &info, &Default::default(), None).unwrap();
+        let binary: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+        let shader = ComposedShader::from_spirv("gradient.spv", binary);
+        let (module, info) = shader
+            .validate_with_info()
+            .map_err(PipelineError::ShaderParseError)?;
+        assert_eq!(module.entry_points[0].stage, naga::ShaderStage::Fragment);
+        let bindings = ShaderBindings::reflect("gradient.spv", &module, &info)?;
+        assert_eq!(
+            bindings.group(0).next().unwrap().entry.visibility,
+            wgpu::ShaderStages::FRAGMENT
+        );
+
+        let shader = ComposedShader::from_spirv("broken.spv", vec![1, 2, 3, 4]);
+        assert!(shader.diagnose().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_device_capabilities() {
+        let load = |_: &str| Ok("fn twice(x: f64) -> f64 {\n    return x * 2.0lf;\n}\n".to_owned());
+        let mut shader = compose_with(
+            &load,
+            &ImportPaths::default(),
+            "double.wgsl",
+            &ShaderDefines::new(),
+        )
+        .unwrap();
+
+        // f64 is not available on every device, only allow it if the device supports it.
+        assert!(shader.validate().is_err());
+        shader.capabilities = device_capabilities(wgpu::Features::empty());
+        assert!(shader.validate().is_err());
+        shader.capabilities = device_capabilities(wgpu::Features::SHADER_F64);
+        assert!(shader.validate().is_ok());
+    }
+
+    #[test]
+    fn test_parse_import() {
+        assert_eq!(
+            parse_import("#import \"common.wgsl\""),
+            Some(Import::File("common.wgsl"))
+        );
+        assert_eq!(
+            parse_import("#import   \"common.wgsl\" // comment"),
+            Some(Import::File("common.wgsl"))
+        );
+        assert_eq!(parse_import("#import"), None);
+        assert_eq!(parse_import("#import common.wgsl"), None);
+        assert_eq!(parse_import("#import \"common.wgsl"), None);
+        assert_eq!(parse_import("#import \"\""), None);
+        assert_eq!(parse_import("#import \"common.wgsl\" garbage"), None);
+    }
+
+    #[test]
+    fn test_parse_module_import() {
+        let module = |path, items| Some(Import::Module { path, items });
+        assert_eq!(
+            parse_import("#import my::noise"),
+            module("my::noise", vec![])
+        );
+        assert_eq!(
+            parse_import("#import my::noise::{fbm, hash} // comment"),
+            module("my::noise", vec!["fbm", "hash"])
+        );
+        assert_eq!(
+            parse_import("#import my::noise::{fbm,}"),
+            module("my::noise", vec!["fbm"])
+        );
+        assert_eq!(parse_import("#import noise"), module("noise", vec![]));
+        assert_eq!(parse_import("#import my::"), None);
+        assert_eq!(parse_import("#import my::noise::{fbm"), None);
+        assert_eq!(parse_import("#import my::noise::{}"), None);
+        assert_eq!(parse_import("#import my::noise garbage"), None);
+    }
+
+    #[test]
+    fn test_module_imports() -> Result<(), PipelineError> {
+        let noise = "test_preprocessor/modules/noise.wgsl";
+        let import_paths = ShaderBuilderFor::<LibraryShaders>::import_paths();
+        let load = ShaderBuilderFor::<LibraryShaders>::load;
+        let declaration = import_paths.resolve("test_preprocessor::noise", &load)?;
+        assert_eq!(declaration.unwrap().file, noise);
+
+        for file in ["items.wgsl", "qualified.wgsl"] {
+            let file = format!("test_preprocessor/modules/{file}");
+            let shader = ShaderBuilderFor::<LibraryShaders>::compose(&file, &ShaderDefines::new())?;
+            assert_eq!(shader.imports[&file], [noise]);
+            assert!(!shader.code.contains("noise::fbm"));
+        }
+
+        let result = ShaderBuilderFor::<LibraryShaders>::build(
+            "test_preprocessor/errors/unknown_module.wgsl",
+        );
+        let Err(PipelineError::ShaderPreprocessError {
+            location, message, ..
+        }) = result
+        else {
+            panic!("expected an unknown module, got {result:?}");
+        };
+        assert_eq!(location.line, 2);
+        assert!(message.contains("test_preprocessor::unknown"), "{message}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_modules() {
+        let load = |name: &str| match name {
+            "dup_a.wgsl" | "dup_b.wgsl" => Ok("#define_import_path my::dup\n".to_owned()),
+            "other.wgsl" => Ok("#define_import_path my::other\nfn other() {}\n".to_owned()),
+            "uses_other.wgsl" => Ok("#import my::other\n".to_owned()),
+            "uses_dup.wgsl" => Ok("#import my::dup\n".to_owned()),
+            _ => Err(PipelineError::InvalidUtf8 {
+                file: name.to_owned(),
+            }),
+        };
+        let files = || {
+            ["dup_a.wgsl", "dup_b.wgsl", "other.wgsl", "broken.wgsl"]
+                .map(str::to_owned)
+                .to_vec()
+        };
+        let compose =
+            |name| compose_with(&load, &ImportPaths::new(files), name, &ShaderDefines::new());
+
+        // Unrelated duplicates and broken files do not fail other imports.
+        let shader = compose("uses_other.wgsl").unwrap();
+        assert_eq!(shader.imports["uses_other.wgsl"], ["other.wgsl"]);
+
+        let result = compose("uses_dup.wgsl");
+        let Err(PipelineError::ShaderPreprocessError {
+            location, message, ..
+        }) = result
+        else {
+            panic!("expected a duplicate module, got {result:?}");
+        };
+        assert_eq!(location.file, "dup_b.wgsl");
+        assert!(message.contains("dup_a.wgsl"), "{message}");
+
+        // The directive must be followed by whitespace.
+        let load =
+            |_: &str| Ok("#define_import_pathfoo\n#define_import_path my::module\n".to_owned());
+        let declarations = find_import_paths(["module.wgsl".to_owned()], &load);
+        assert_eq!(declarations.keys().collect::<Vec<_>>(), ["my::module"]);
+    }
+
+    #[test]
+    fn test_shader_changes() -> Result<(), PipelineError> {
+        let compute = "demos/boids/compute.wgsl";
+        let draw = "demos/raymarching/draw.wgsl";
+        ShaderBuilderFor::<LibraryShaders>::compose(
+            compute,
+            &boids::Pipeline::compute_shader_defines(),
+        )?;
+        ShaderBuilderFor::<LibraryShaders>::compose(
+            draw,
+            &raymarching::Pipeline::shader_defines(false),
+        )?;
+
+        // Imports are dependencies too, but files excluded by #ifdef are not.
+        let changes = |file: &str| ShaderChanges::Files(vec![file.to_owned()]);
+        assert!(changes(compute).affects(compute));
+        assert!(!changes(compute).affects(draw));
+        assert!(changes("demos/raymarching/camera.wgsl").affects(draw));
+        assert!(!changes("demos/raymarching/draw_2d.wgsl").affects(draw));
+        assert!(ShaderChanges::All.affects(compute));
+
+        // Shaders that were never built are always affected.
+        assert!(changes(compute).affects("demos/polygon/never_built.wgsl"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_qualifiers() {
+        let qualifiers = ["my::noise::".to_owned(), "noise::".to_owned()];
+        let strip = |line| strip_qualifiers(line, &qualifiers);
+        assert_eq!(strip("noise::fbm(my::noise::hash(p))"), "fbm(hash(p))");
+        assert_eq!(strip("other_noise::fbm(p)"), "other_noise::fbm(p)");
+        assert_eq!(strip("your::noise::fbm(p)"), "your::noise::fbm(p)");
+    }
+
+    #[test]
+    fn test_resolve_import() {
+        let importer = "demos/raymarching/draw.wgsl";
+        let resolve = |include| resolve_import(importer, include);
+        assert_eq!(
+            resolve("./camera.wgsl").unwrap(),
+            "demos/raymarching/camera.wgsl"
+        );
+        assert_eq!(
+            resolve("../shared/noise.wgsl").unwrap(),
+            "demos/shared/noise.wgsl"
+        );
+        assert_eq!(resolve("common.wgsl").unwrap(), "common.wgsl");
+        assert_eq!(
+            resolve("/demos/./common.wgsl").unwrap(),
+            "demos/common.wgsl"
+        );
+        assert_eq!(resolve("../../../noise.wgsl"), None);
+        assert_eq!(resolve("../../noise.wgsl").unwrap(), "noise.wgsl");
+        assert_eq!(resolve("demos/../../noise.wgsl"), None);
+
+        // Relative imports keep the prefix of the importer, and cannot escape its root.
+        let resolve = |include| resolve_import("shared:noise/simplex.wgsl", include);
+        assert_eq!(resolve("./hash.wgsl").unwrap(), "shared:noise/hash.wgsl");
+        assert_eq!(resolve("../math.wgsl").unwrap(), "shared:math.wgsl");
+        assert_eq!(resolve("../../math.wgsl"), None);
+        assert_eq!(resolve("common.wgsl").unwrap(), "common.wgsl");
+        assert_eq!(resolve("shared:/./math.wgsl").unwrap(), "shared:math.wgsl");
+        assert_eq!(resolve("rust:CameraLookAt").unwrap(), "rust:CameraLookAt");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_shader_root_override() {
+        #[derive(RustEmbed)]
+        #[folder = "../shared_shaders"]
+        struct Overridden;
+        impl ShaderFolder for Overridden {
+            const ROOT_VAR: Option<&'static str> = Some("TEST_SHADER_ROOT");
+        }
+
+        #[derive(RustEmbed)]
+        #[folder = "../shared_shaders"]
+        struct Embedded;
+        impl ShaderFolder for Embedded {}
+
+        // Only the folders opting in are read from the override.
+        std::env::set_var("TEST_SHADER_ROOT", LIBRARY_SHADERS_FOLDER);
+        assert!(ShaderBuilderFor::<Overridden>::load("demos/polygon/draw.wgsl").is_ok());
+        assert!(ShaderBuilderFor::<Overridden>::load("math.wgsl").is_err());
+        assert!(ShaderBuilderFor::<Embedded>::load("demos/polygon/draw.wgsl").is_err());
+        assert!(ShaderBuilderFor::<Embedded>::load("math.wgsl").is_ok());
+    }
+
+    #[test]
+    fn test_shader_roots() {
+        assert!(prefixed_files().contains(&"shared:math.wgsl".to_owned()));
+        assert!(ShaderBuilderFor::<LibraryShaders>::load("shared:math.wgsl")
+            .unwrap()
+            .contains("const PI"));
+        assert!(matches!(
+            ShaderBuilderFor::<LibraryShaders>::load("shared:missing.wgsl"),
+            Err(PipelineError::ShaderNotFound { file }) if file == "shared:missing.wgsl"
+        ));
+        assert!(load_prefixed("demos/polygon/draw.wgsl").is_none());
+
+        // Changes to the shared library rebuild the shaders importing it.
+        ShaderBuilderFor::<LibraryShaders>::build("demos/polygon/draw.wgsl").unwrap();
+        assert!(shader_dependencies("demos/polygon/draw.wgsl")
+            .unwrap()
+            .contains(&"shared:math.wgsl".to_owned()));
+    }
+
+    #[test]
+    fn test_import_cycle() {
+        let result =
+            ShaderBuilderFor::<LibraryShaders>::build("test_preprocessor/errors/cycle_a.wgsl");
+        let Err(PipelineError::ImportCycle { chain }) = result else {
+            panic!("expected an import cycle, got {result:?}");
+        };
+        assert_eq!(
+            chain,
+            [
+                "test_preprocessor/errors/cycle_a.wgsl",
+                "test_preprocessor/errors/cycle_b.wgsl",
+                "test_preprocessor/errors/cycle_a.wgsl",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_endif() {
+        let result =
+            ShaderBuilderFor::<LibraryShaders>::build("test_preprocessor/unbalanced_endif.wgsl");
+        let Err(error @ PipelineError::ShaderPreprocessError { .. }) = result else {
+            panic!("expected a preprocess error, got {result:?}");
+        };
+        assert_eq!(error.location().unwrap().line, 3);
+        // The overlay displays the offending line.
+        assert_eq!(error.snippet(), Some("#endif"));
+        assert_eq!(
+            error.file(),
+            Some("test_preprocessor/unbalanced_endif.wgsl")
+        );
+    }
 }
@@ -0,0 +1,504 @@
+//! Compare the layout of rust structs sent to the gpu with the wgsl structs reading them,
+//! to catch padding mismatches when pipelines are created rather than on screen.
+//!
+//! Both structs are flattened into scalars: `[f32; 2]` and `vec2<f32>` are the same two floats.
+//! Fields whose name starts with `_` are considered padding, on both sides.
+
+use crate::pipeline::PipelineError;
+
+/// Scalar of a flattened struct.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutScalar {
+    /// Path of the scalar in the struct, such as `camera.center[0]`.
+    pub name: String,
+    /// Offset in bytes from the start of the struct.
+    pub offset: usize,
+    pub scalar: naga::Scalar,
+    /// Whether the scalar belongs to a padding field.
+    pub padding: bool,
+}
+
+/// Rules used to lay out a wgsl struct, depending on the buffer it is read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutRules {
+    /// `var<uniform>`: arrays strides and nested structs are aligned to 16 bytes.
+    Uniform,
+    /// `var<storage>`.
+    Storage,
+}
+
+/// Rust type shared with shaders, describing its fields.
+/// Implement it for structs with `#[derive(WgslStruct)]` or [`impl_struct_layout!`](crate::impl_struct_layout).
+pub trait StructLayout {
+    /// Append the scalars of the type located at `offset`, named after `name`.
+    fn scalars(name: &str, offset: usize, padding: bool, scalars: &mut Vec<LayoutScalar>);
+}
+
+/// Implement [`StructLayout`] for a `#[repr(C)]` struct by listing all its fields, padding included.
+/// Forgetting a field fails to compile.
+///
+/// ```ignore
+/// impl_struct_layout!(RaymarchingSettings { camera, size, elapsed, _padding });
+/// ```
+#[macro_export]
+macro_rules! impl_struct_layout {
+    ($struct:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::struct_layout::StructLayout for $struct {
+            fn scalars(
+                name: &str,
+                offset: usize,
+                padding: bool,
+                scalars: &mut Vec<$crate::struct_layout::LayoutScalar>,
+            ) {
+                // Make sure every field is listed.
+                let _ = |value: &Self| {
+                    let Self { $($field: _),* } = value;
+                };
+                $(
+                    $crate::struct_layout::field_scalars(
+                        |value: &Self| &value.$field,
+                        name,
+                        stringify!($field),
+                        offset + std::mem::offset_of!(Self, $field),
+                        padding,
+                        scalars,
+                    );
+                )*
+            }
+        }
+    };
+}
+
+/// Append the scalars of a struct field, used by [`impl_struct_layout!`](crate::impl_struct_layout).
+/// `_field` only infers the field type.
+pub fn field_scalars<S, F: StructLayout>(
+    _field: fn(&S) -> &F,
+    name: &str,
+    field: &str,
+    offset: usize,
+    padding: bool,
+    scalars: &mut Vec<LayoutScalar>,
+) {
+    let name = if name.is_empty() {
+        field.to_owned()
+    } else {
+        format!("{name}.{field}")
+    };
+    F::scalars(&name, offset, padding || field.starts_with('_'), scalars);
+}
+
+macro_rules! impl_scalar_layout {
+    ($($ty:ty => $scalar:expr),*) => {
+        $(
+            impl StructLayout for $ty {
+                fn scalars(name: &str, offset: usize, padding: bool, scalars: &mut Vec<LayoutScalar>) {
+                    scalars.push(LayoutScalar {
+                        name: name.to_owned(),
+                        offset,
+                        scalar: $scalar,
+                        padding,
+                    });
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar_layout!(f32 => naga::Scalar::F32, i32 => naga::Scalar::I32, u32 => naga::Scalar::U32);
+
+impl<T: StructLayout, const N: usize> StructLayout for [T; N] {
+    fn scalars(name: &str, offset: usize, padding: bool, scalars: &mut Vec<LayoutScalar>) {
+        for index in 0..N {
+            T::scalars(
+                &format!("{name}[{index}]"),
+                offset + index * std::mem::size_of::<T>(),
+                padding,
+                scalars,
+            );
+        }
+    }
+}
+
+/// Check that the rust struct `T` has the same layout as the wgsl struct `wgsl_struct` of `module`:
+/// - every wgsl field is read from a rust field of the same type at the same offset,
+/// - every rust field is read by a wgsl field, possibly a padding one,
+/// - both structs have the same size,
+/// - the wgsl struct follows the uniform buffer alignment rules if `rules` is `Uniform`.
+///
+/// # Errors
+/// - [`PipelineError::StructLayoutMismatch`] listing the differences.
+pub fn check_struct_layout<T: StructLayout + bytemuck::Pod>(
+    shader: &str,
+    module: &naga::Module,
+    wgsl_struct: &str,
+    rules: LayoutRules,
+) -> Result<(), PipelineError> {
+    let rust_struct = std::any::type_name::<T>();
+    let rust_struct = rust_struct.rsplit("::").next().unwrap_or(rust_struct);
+    let error = |mismatches| PipelineError::StructLayoutMismatch {
+        shader: shader.to_owned(),
+        rust_struct: rust_struct.to_owned(),
+        wgsl_struct: wgsl_struct.to_owned(),
+        mismatches,
+    };
+
+    let Some((ty, _)) = module
+        .types
+        .iter()
+        .find(|(_, ty)| ty.name.as_deref() == Some(wgsl_struct))
+    else {
+        return Err(error(vec![format!("struct {wgsl_struct} not found")]));
+    };
+
+    let mut layouter = naga::proc::Layouter::default();
+    layouter
+        .update(module.to_ctx())
+        .map_err(|layout_error| error(vec![layout_error.to_string()]))?;
+
+    let mut mismatches = vec![];
+    let mut wgsl_scalars = vec![];
+    wgsl_type_scalars(
+        module,
+        ty,
+        "",
+        0,
+        false,
+        rules,
+        &mut wgsl_scalars,
+        &mut mismatches,
+    );
+    let mut rust_scalars = vec![];
+    T::scalars("", 0, false, &mut rust_scalars);
+
+    let describe = |scalar: &LayoutScalar| {
+        format!(
+            "`{}` ({} at offset {})",
+            scalar.name,
+            scalar_name(scalar.scalar),
+            scalar.offset
+        )
+    };
+
+    for wgsl in wgsl_scalars.iter().filter(|scalar| !scalar.padding) {
+        match rust_scalars.iter().find(|rust| rust.offset == wgsl.offset) {
+            None => mismatches.push(format!(
+                "wgsl {} does not match any rust field",
+                describe(wgsl)
+            )),
+            Some(rust) if rust.padding => mismatches.push(format!(
+                "wgsl {} is read from rust padding {}",
+                describe(wgsl),
+                describe(rust)
+            )),
+            Some(rust) if rust.scalar != wgsl.scalar => mismatches.push(format!(
+                "wgsl {} is read from rust {}",
+                describe(wgsl),
+                describe(rust)
+            )),
+            Some(_) => {}
+        }
+    }
+
+    for rust in rust_scalars.iter().filter(|scalar| !scalar.padding) {
+        if !wgsl_scalars.iter().any(|wgsl| wgsl.offset == rust.offset) {
+            mismatches.push(format!(
+                "rust {} is not read by the shader, it falls in wgsl padding",
+                describe(rust)
+            ));
+        }
+    }
+
+    let rust_size = std::mem::size_of::<T>();
+    let wgsl_size = layouter[ty].size as usize;
+    if rust_size != wgsl_size {
+        mismatches.push(format!(
+            "rust struct is {rust_size} bytes but wgsl struct is {wgsl_size} bytes"
+        ));
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(error(mismatches))
+    }
+}
+
+/// Wgsl name of a scalar type.
+fn scalar_name(scalar: naga::Scalar) -> String {
+    match scalar.kind {
+        naga::ScalarKind::Sint => format!("i{}", scalar.width * 8),
+        naga::ScalarKind::Uint => format!("u{}", scalar.width * 8),
+        naga::ScalarKind::Float => format!("f{}", scalar.width * 8),
+        naga::ScalarKind::Bool => "bool".to_owned(),
+        naga::ScalarKind::AbstractInt | naga::ScalarKind::AbstractFloat => "abstract".to_owned(),
+    }
+}
+
+/// Append the scalars of a wgsl type located at `offset`,
+/// and the violations of the uniform alignment rules to `mismatches`.
+#[allow(clippy::too_many_arguments)]
+fn wgsl_type_scalars(
+    module: &naga::Module,
+    ty: naga::Handle<naga::Type>,
+    name: &str,
+    offset: usize,
+    padding: bool,
+    rules: LayoutRules,
+    scalars: &mut Vec<LayoutScalar>,
+    mismatches: &mut Vec<String>,
+) {
+    let mut push = |name: String, offset: usize, scalar: naga::Scalar| {
+        scalars.push(LayoutScalar {
+            name,
+            offset,
+            scalar,
+            padding,
+        });
+    };
+
+    const COMPONENTS: [&str; 4] = ["x", "y", "z", "w"];
+    match module.types[ty].inner {
+        naga::TypeInner::Scalar(scalar) | naga::TypeInner::Atomic(scalar) => {
+            push(name.to_owned(), offset, scalar);
+        }
+        naga::TypeInner::Vector { size, scalar } => {
+            for (index, component) in COMPONENTS.iter().take(size as usize).enumerate() {
+                let offset = offset + index * scalar.width as usize;
+                push(format!("{name}.{component}"), offset, scalar);
+            }
+        }
+        naga::TypeInner::Matrix {
+            columns,
+            rows,
+            scalar,
+        } => {
+            // Columns are aligned like vectors: a vec3 column takes 16 bytes.
+            let rows = rows as usize;
+            let column_stride = rows.next_power_of_two() * scalar.width as usize;
+            for column in 0..columns as usize {
+                for (row, component) in COMPONENTS.iter().take(rows).enumerate() {
+                    let offset = offset + column * column_stride + row * scalar.width as usize;
+                    push(format!("{name}[{column}].{component}"), offset, scalar);
+                }
+            }
+        }
+        naga::TypeInner::Array { base, size, stride } => {
+            if rules == LayoutRules::Uniform && !stride.is_multiple_of(16) {
+                mismatches.push(format!(
+                    "wgsl `{name}` has a stride of {stride} bytes, uniform buffers require a multiple of 16"
+                ));
+            }
+            let naga::ArraySize::Constant(count) = size else {
+                mismatches.push(format!(
+                    "wgsl `{name}` is a runtime sized array, which has no rust equivalent"
+                ));
+                return;
+            };
+            for index in 0..count.get() as usize {
+                wgsl_type_scalars(
+                    module,
+                    base,
+                    &format!("{name}[{index}]"),
+                    offset + index * stride as usize,
+                    padding,
+                    rules,
+                    scalars,
+                    mismatches,
+                );
+            }
+        }
+        naga::TypeInner::Struct { ref members, .. } => {
+            // Name and end of the previous member if it is a struct.
+            let mut previous_struct: Option<(String, usize)> = None;
+            for member in members {
+                let member_name = member.name.clone().unwrap_or_default();
+                let member_offset = offset + member.offset as usize;
+                let member_type = &module.types[member.ty].inner;
+                let is_struct = matches!(member_type, naga::TypeInner::Struct { .. });
+                if rules == LayoutRules::Uniform && is_struct && !member_offset.is_multiple_of(16) {
+                    mismatches.push(format!(
+                        "wgsl `{member_name}` is a struct at offset {member_offset}, uniform buffers require a multiple of 16"
+                    ));
+                }
+                if let Some((previous, end)) = previous_struct.take() {
+                    let required = end.next_multiple_of(16);
+                    if rules == LayoutRules::Uniform && member_offset < required {
+                        mismatches.push(format!(
+                            "wgsl `{member_name}` is at offset {member_offset} after the struct `{previous}`, uniform buffers require offset {required}"
+                        ));
+                    }
+                }
+                if is_struct {
+                    let end = member_offset + member_type.size(module.to_ctx()) as usize;
+                    previous_struct = Some((member_name.clone(), end));
+                }
+                let path = if name.is_empty() {
+                    member_name.clone()
+                } else {
+                    format!("{name}.{member_name}")
+                };
+                wgsl_type_scalars(
+                    module,
+                    member.ty,
+                    &path,
+                    member_offset,
+                    padding || member_name.starts_with('_'),
+                    rules,
+                    scalars,
+                    mismatches,
+                );
+            }
+        }
+        _ => mismatches.push(format!("wgsl `{name}` is not host-shareable")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera_control::CameraLookAt;
+    use crate::demo_pipelines::{boids, polygon, raymarching};
+    use crate::{ShaderBuilderForLibrary, ShaderDefines};
+
+    #[repr(C)]
+    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+    struct Settings {
+        elapsed: f32,
+        size: [f32; 2],
+        count: u32,
+    }
+    crate::impl_struct_layout!(Settings {
+        elapsed,
+        size,
+        count
+    });
+
+    fn mismatches<T: StructLayout + bytemuck::Pod>(wgsl: &str, rules: LayoutRules) -> Vec<String> {
+        let module = naga::front::wgsl::parse_str(wgsl).unwrap();
+        match check_struct_layout::<T>("test.wgsl", &module, "Settings", rules) {
+            Ok(()) => vec![],
+            Err(PipelineError::StructLayoutMismatch { mismatches, .. }) => mismatches,
+            Err(error) => panic!("unexpected error {error}"),
+        }
+    }
+
+    #[test]
+    fn test_demo_layouts() -> Result<(), PipelineError> {
+        let check = |shader: &str, defines: &ShaderDefines| {
+            ShaderBuilderForLibrary::validate(shader, defines)
+                .map(|module| (shader.to_owned(), module))
+        };
+
+        for raymarching_2d in [false, true] {
+            let defines = raymarching::Pipeline::shader_defines(raymarching_2d);
+            let (shader, module) = check("demos/raymarching/draw.wgsl", &defines)?;
+            check_struct_layout::<raymarching::RaymarchingSettings>(
+                &shader,
+                &module,
+                "Uniforms",
+                LayoutRules::Uniform,
+            )?;
+        }
+
+        let (shader, module) = check("demos/polygon/draw.wgsl", &ShaderDefines::new())?;
+        check_struct_layout::<polygon::PolygonSettings>(
+            &shader,
+            &module,
+            "PolygonSettings",
+            LayoutRules::Uniform,
+        )?;
+
+        let defines = boids::Pipeline::compute_shader_defines();
+        let (shader, module) = check("demos/boids/compute.wgsl", &defines)?;
+        check_struct_layout::<boids::BoidsSettings>(
+            &shader,
+            &module,
+            "BoidsSettings",
+            LayoutRules::Uniform,
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_mismatch() {
+        // vec2 is aligned to 8 bytes while [f32; 2] is aligned to 4.
+        let wgsl = "struct Settings { elapsed: f32, size: vec2<f32>, count: i32 }";
+        assert_eq!(
+            mismatches::<Settings>(wgsl, LayoutRules::Storage),
+            [
+                "wgsl `size.y` (f32 at offset 12) is read from rust `count` (u32 at offset 12)",
+                "wgsl `count` (i32 at offset 16) does not match any rust field",
+                "rust `size[0]` (f32 at offset 4) is not read by the shader, it falls in wgsl padding",
+                "rust struct is 16 bytes but wgsl struct is 24 bytes",
+            ]
+        );
+
+        let wgsl = "struct Settings { elapsed: f32, size: array<f32, 2>, count: u32 }";
+        assert!(mismatches::<Settings>(wgsl, LayoutRules::Storage).is_empty());
+        assert_eq!(
+            mismatches::<Settings>(wgsl, LayoutRules::Uniform),
+            ["wgsl `size` has a stride of 4 bytes, uniform buffers require a multiple of 16"]
+        );
+
+        // Rust fields can be ignored by wgsl padding fields, but not by implicit padding.
+        let wgsl = "struct Settings { elapsed: f32, _padding: array<f32, 2>, count: u32 }";
+        assert!(mismatches::<Settings>(wgsl, LayoutRules::Storage).is_empty());
+        let wgsl = "struct Settings { elapsed: f32, _padding: vec2<f32>, count: u32 }";
+        assert_eq!(
+            mismatches::<Settings>(wgsl, LayoutRules::Storage),
+            [
+                "wgsl `count` (u32 at offset 16) does not match any rust field",
+                "rust `size[0]` (f32 at offset 4) is not read by the shader, it falls in wgsl padding",
+                "rust struct is 16 bytes but wgsl struct is 24 bytes",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_member_after_struct() {
+        #[repr(C)]
+        #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+        struct Inner {
+            value: f32,
+        }
+        crate::impl_struct_layout!(Inner { value });
+
+        #[repr(C)]
+        #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+        struct Nested {
+            inner: Inner,
+            count: u32,
+        }
+        crate::impl_struct_layout!(Nested { inner, count });
+
+        let wgsl = "struct Inner { value: f32 } struct Settings { inner: Inner, count: u32 }";
+        assert!(mismatches::<Nested>(wgsl, LayoutRules::Storage).is_empty());
+        assert_eq!(
+            mismatches::<Nested>(wgsl, LayoutRules::Uniform),
+            ["wgsl `count` is at offset 4 after the struct `inner`, uniform buffers require offset 16"]
+        );
+    }
+
+    #[test]
+    fn test_nested_layout() {
+        let mut scalars = vec![];
+        CameraLookAt::scalars("camera", 16, false, &mut scalars);
+        let names: Vec<_> = scalars
+            .iter()
+            .map(|scalar| (scalar.name.as_str(), scalar.offset))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("camera.center[0]", 16),
+                ("camera.center[1]", 20),
+                ("camera.center[2]", 24),
+                ("camera.center[3]", 28),
+                ("camera.longitude", 32),
+                ("camera.latitude", 36),
+                ("camera.distance", 40),
+            ]
+        );
+    }
+}
@@ -0,0 +1,400 @@
+//! Fingerprint of the layout of the types crossing the library boundary.
+//!
+//! Values of these types are used on both sides of a rust reload: the runner owns the input helper,
+//! updates the camera and displays the errors with its own code, compiled with the original library.
+//! If a reload changes their layout, each side reads them with different offsets.
+//! The runner compares the fingerprints before and after each reload instead,
+//! see `layout_fingerprint` in `lib.rs`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// Type whose layout is part of the fingerprint.
+/// Implement it with [`impl_type_layout!`](crate::impl_type_layout).
+/// Every field of the type must implement it too, so a change nested in a field changes the fingerprint.
+pub trait TypeLayout {
+    fn describe() -> LayoutDescription;
+}
+
+/// Size, alignment and fields of a type.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LayoutDescription {
+    pub name: &'static str,
+    pub size: usize,
+    pub align: usize,
+    /// Fields in declaration order, with the fields of each variant for enums,
+    /// and the type parameters of generic containers such as `Vec<T>`.
+    /// Empty for types described as a whole, such as wgpu handles.
+    pub fields: Vec<FieldDescription>,
+    /// Variants of an enum, in declaration order.
+    pub variants: Vec<&'static str>,
+}
+
+impl LayoutDescription {
+    /// Describe `T` as a whole, without its fields.
+    pub fn opaque<T>() -> Self {
+        Self {
+            name: std::any::type_name::<T>(),
+            size: std::mem::size_of::<T>(),
+            align: std::mem::align_of::<T>(),
+            fields: vec![],
+            variants: vec![],
+        }
+    }
+}
+
+/// Field of a [`LayoutDescription`], described down to its own fields.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FieldDescription {
+    /// Name of the field, `Variant.field` for the fields of an enum variant.
+    pub name: &'static str,
+    /// Offset in the struct, `None` for the fields of enum variants and type parameters,
+    /// whose offsets cannot be queried: the layouts they are made of stand for them.
+    pub offset: Option<usize>,
+    pub layout: LayoutDescription,
+}
+
+/// Implement [`TypeLayout`] for a struct by listing all its fields.
+/// Forgetting a field fails to compile.
+///
+/// Enums list their variants along with the name and type of each field,
+/// which must match the declaration:
+///
+/// ```ignore
+/// impl_type_layout!(CameraLookAt { center, longitude, latitude, distance });
+/// impl_type_layout!(enum ShaderChanges { All, Files(files: Vec<String>) });
+/// ```
+#[macro_export]
+macro_rules! impl_type_layout {
+    (enum $enum:ty {
+        $($variant:ident
+            $(( $($tuple:ident: $tuple_ty:ty),* $(,)? ))?
+            $({ $($named:ident: $named_ty:ty),* $(,)? })?
+        ),* $(,)?
+    }) => {
+        impl $crate::type_layout::TypeLayout for $enum {
+            fn describe() -> $crate::type_layout::LayoutDescription {
+                // Make sure every variant and field is listed with its type.
+                let _ = |value: &Self| match value {
+                    $(
+                        Self::$variant $(( $($tuple),* ))? $({ $($named),* })? => {
+                            $($(let _: &$tuple_ty = $tuple;)*)?
+                            $($(let _: &$named_ty = $named;)*)?
+                        }
+                    )*
+                };
+                $crate::type_layout::LayoutDescription {
+                    fields: vec![
+                        $(
+                            $($(
+                                $crate::type_layout::describe_variant_field::<$tuple_ty>(
+                                    concat!(stringify!($variant), ".", stringify!($tuple)),
+                                ),
+                            )*)?
+                            $($(
+                                $crate::type_layout::describe_variant_field::<$named_ty>(
+                                    concat!(stringify!($variant), ".", stringify!($named)),
+                                ),
+                            )*)?
+                        )*
+                    ],
+                    variants: vec![$(stringify!($variant)),*],
+                    ..$crate::type_layout::LayoutDescription::opaque::<Self>()
+                }
+            }
+        }
+    };
+    ($struct:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::type_layout::TypeLayout for $struct {
+            fn describe() -> $crate::type_layout::LayoutDescription {
+                // Make sure every field is listed.
+                let _ = |value: &Self| {
+                    let Self { $($field: _),* } = value;
+                };
+                $crate::type_layout::LayoutDescription {
+                    fields: vec![
+                        $(
+                            $crate::type_layout::describe_field(
+                                |value: &Self| &value.$field,
+                                stringify!($field),
+                                std::mem::offset_of!(Self, $field),
+                            ),
+                        )*
+                    ],
+                    ..$crate::type_layout::LayoutDescription::opaque::<Self>()
+                }
+            }
+        }
+    };
+}
+
+/// Describe a struct field, used by [`impl_type_layout!`](crate::impl_type_layout).
+/// `_field` only infers the field type.
+pub fn describe_field<S, F: TypeLayout>(
+    _field: fn(&S) -> &F,
+    name: &'static str,
+    offset: usize,
+) -> FieldDescription {
+    FieldDescription {
+        name,
+        offset: Some(offset),
+        layout: F::describe(),
+    }
+}
+
+/// Describe a field of an enum variant, used by [`impl_type_layout!`](crate::impl_type_layout).
+pub fn describe_variant_field<F: TypeLayout>(name: &'static str) -> FieldDescription {
+    FieldDescription {
+        name,
+        offset: None,
+        layout: F::describe(),
+    }
+}
+
+/// Describe a type parameter of a generic container.
+fn describe_param<T: TypeLayout>(name: &'static str) -> FieldDescription {
+    FieldDescription {
+        name,
+        offset: None,
+        layout: T::describe(),
+    }
+}
+
+/// Implement [`TypeLayout`] for types described as a whole:
+/// primitives, and foreign types whose layout does not change with a library reload.
+macro_rules! impl_opaque_layout {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl TypeLayout for $ty {
+                fn describe() -> LayoutDescription {
+                    LayoutDescription::opaque::<Self>()
+                }
+            }
+        )*
+    };
+}
+
+impl_opaque_layout!(
+    bool,
+    u32,
+    u64,
+    i32,
+    usize,
+    f32,
+    f64,
+    String,
+    std::path::PathBuf,
+    std::time::Duration,
+    web_time::Instant,
+    winit::keyboard::Key,
+    winit::keyboard::PhysicalKey,
+    winit::event::MouseButton,
+    wgpu::BindGroup,
+    wgpu::Buffer,
+    wgpu::ComputePipeline,
+    wgpu::RenderPipeline,
+    wgpu::Features,
+    wgpu::DownlevelCapabilities,
+    wgpu::Limits,
+);
+
+/// Implement [`TypeLayout`] for generic containers, also describing their type parameters.
+macro_rules! impl_generic_layout {
+    ($($ty:ident<$($param:ident),+>),* $(,)?) => {
+        $(
+            impl<$($param: TypeLayout),+> TypeLayout for $ty<$($param),+> {
+                fn describe() -> LayoutDescription {
+                    LayoutDescription {
+                        fields: vec![$(describe_param::<$param>(stringify!($param))),+],
+                        ..LayoutDescription::opaque::<Self>()
+                    }
+                }
+            }
+        )*
+    };
+}
+
+use winit::dpi::PhysicalSize;
+impl_generic_layout!(Option<T>, Vec<T>, PhysicalSize<T>, HashMap<K, V>, BTreeMap<K, V>);
+
+impl<T: TypeLayout, const N: usize> TypeLayout for [T; N] {
+    fn describe() -> LayoutDescription {
+        LayoutDescription {
+            fields: vec![describe_param::<T>("T")],
+            ..LayoutDescription::opaque::<Self>()
+        }
+    }
+}
+
+impl<A: TypeLayout, B: TypeLayout> TypeLayout for (A, B) {
+    fn describe() -> LayoutDescription {
+        LayoutDescription {
+            fields: vec![
+                FieldDescription {
+                    offset: Some(std::mem::offset_of!(Self, 0)),
+                    ..describe_param::<A>("0")
+                },
+                FieldDescription {
+                    offset: Some(std::mem::offset_of!(Self, 1)),
+                    ..describe_param::<B>("1")
+                },
+            ],
+            ..LayoutDescription::opaque::<Self>()
+        }
+    }
+}
+
+/// FNV-1a hasher: unlike `DefaultHasher`, its output does not change between builds,
+/// so fingerprints of two builds of the library can be compared.
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Hash of the layouts of `types`.
+/// Layouts, and the type names they include, are only stable for a given compiler.
+pub fn fingerprint(types: &[LayoutDescription]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    types.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Fingerprints of the types crossing the library boundary, compared by the runner after each reload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayoutFingerprint {
+    /// `CurrentPipeline` and the registered pipelines: if it changes, the runner recreates the pipeline.
+    pub pipeline: u64,
+    /// Types the runner exchanges with the library, such as `CameraLookAt` or `PipelineError`,
+    /// also used by the runner code, which cannot be reloaded: if it changes, the app must be restarted.
+    pub boundary: u64,
+}
+
+crate::impl_type_layout!(LayoutFingerprint { pipeline, boundary });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CameraLookAt;
+
+    #[allow(dead_code)]
+    struct Settings {
+        speed: f32,
+        center: [f32; 2],
+    }
+
+    #[allow(dead_code)]
+    struct Reordered {
+        center: [f32; 2],
+        speed: f32,
+    }
+
+    #[allow(dead_code)]
+    struct Nested {
+        settings: Settings,
+    }
+
+    #[allow(dead_code)]
+    struct NestedReordered {
+        settings: Reordered,
+    }
+
+    #[allow(dead_code)]
+    enum Changes {
+        All,
+        Files(Vec<String>),
+        Settings { settings: Settings },
+    }
+
+    crate::impl_type_layout!(Settings { speed, center });
+    crate::impl_type_layout!(Reordered { center, speed });
+    crate::impl_type_layout!(Nested { settings });
+    crate::impl_type_layout!(NestedReordered { settings });
+    crate::impl_type_layout!(enum Changes {
+        All,
+        Files(files: Vec<String>),
+        Settings { settings: Settings },
+    });
+
+    #[test]
+    fn test_describe_layout() {
+        let layout = Settings::describe();
+        assert!(layout.name.ends_with("Settings"));
+        assert_eq!((layout.size, layout.align), (12, 4));
+        let fields: Vec<_> = layout
+            .fields
+            .iter()
+            .map(|field| (field.name, field.layout.size, field.layout.name))
+            .collect();
+        assert_eq!(fields, [("speed", 4, "f32"), ("center", 8, "[f32; 2]")]);
+        assert!(layout.fields.iter().all(|field| field.offset.is_some()));
+
+        let camera = CameraLookAt::describe();
+        assert_eq!(camera.size, std::mem::size_of::<CameraLookAt>());
+        assert_eq!(camera.fields.len(), 4);
+
+        // Nested types are described down to their own fields.
+        let nested = Nested::describe();
+        assert_eq!(nested.fields[0].layout, Settings::describe());
+
+        let changes = Changes::describe();
+        assert_eq!(changes.variants, ["All", "Files", "Settings"]);
+        let fields: Vec<_> = changes
+            .fields
+            .iter()
+            .map(|field| (field.name, field.offset))
+            .collect();
+        assert_eq!(fields, [("Files.files", None), ("Settings.settings", None)]);
+        assert_eq!(changes.fields[1].layout, Settings::describe());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let settings = fingerprint(&[Settings::describe()]);
+        assert_eq!(settings, fingerprint(&[Settings::describe()]));
+
+        // Same size and fields, in another order.
+        let mut reordered = Reordered::describe();
+        reordered.name = Settings::describe().name;
+        assert_ne!(settings, fingerprint(&[reordered.clone()]));
+
+        assert_ne!(
+            settings,
+            fingerprint(&[Settings::describe(), CameraLookAt::describe()])
+        );
+
+        // A change nested in a field changes the fingerprint of the outer type.
+        let nested = fingerprint(&[Nested::describe()]);
+        let mut nested_reordered = NestedReordered::describe();
+        nested_reordered.name = Nested::describe().name;
+        nested_reordered.fields[0].layout = reordered;
+        assert_ne!(nested, fingerprint(&[nested_reordered]));
+    }
+
+    #[test]
+    fn test_stable_hasher() {
+        // Reference FNV-1a values, the same for every build.
+        let hash = |bytes: &[u8]| {
+            let mut hasher = FnvHasher::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,377 @@
+//! Wgsl declarations generated from rust structs with `#[derive(WgslStruct)]`,
+//! so the struct is only written once and the shader version cannot drift.
+//!
+//! Shaders import the generated declaration like a file, with `#import "rust:PolygonSettings"`.
+//! Deriving the struct registers it, see [`RustStruct`].
+//!
+//! `[T; 2..=4]` of scalars become vectors, other arrays become `array<T, N>`.
+//! Members are placed at the rust offsets with `@size`, and fields whose name starts with `_`
+//! are left out as padding. A field the wgsl alignment rules cannot place at its rust offset
+//! fails the import with a [`PipelineError::StructLayoutMismatch`].
+
+pub use wgsl_derive::WgslStruct;
+// Lets `#[derive(WgslStruct)]` register structs without depending on inventory.
+#[doc(hidden)]
+pub use inventory;
+
+use crate::pipeline::PipelineError;
+
+/// Prefix of the imports resolved to rust structs rather than files.
+pub const RUST_IMPORT_PREFIX: &str = "rust:";
+
+/// Rust type with a wgsl equivalent, implemented by `#[derive(WgslStruct)]` for structs.
+pub trait WgslType {
+    /// Wgsl type, such as `vec2<f32>` or the struct name.
+    fn wgsl_type() -> String;
+    /// Alignment of the wgsl type, in bytes.
+    fn wgsl_align() -> usize;
+    /// Size of the wgsl type, in bytes.
+    fn wgsl_size() -> usize;
+    /// Whether arrays of this type are vectors.
+    fn is_scalar() -> bool {
+        false
+    }
+    /// Imports declaring the structs used by the type.
+    fn wgsl_imports(_imports: &mut Vec<String>) {}
+}
+
+/// Rust struct with a generated wgsl declaration, see [`wgsl_declaration`].
+pub trait WgslStruct: WgslType + Sized {
+    /// Name of the struct in rust and wgsl.
+    const WGSL_NAME: &'static str;
+    /// Fields of the struct, in declaration order.
+    fn wgsl_fields() -> Vec<WgslField>;
+}
+
+/// Field of a struct deriving [`WgslStruct`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct WgslField {
+    pub name: &'static str,
+    /// Offset of the field in the rust struct.
+    pub offset: usize,
+    /// Wgsl type of the field.
+    pub ty: String,
+    /// Alignment of the wgsl type.
+    pub align: usize,
+    /// Size of the wgsl type.
+    pub size: usize,
+    /// Imports declaring the structs used by the field.
+    pub imports: Vec<String>,
+}
+
+impl WgslField {
+    /// Describe a field of type `T` located at `offset` in the rust struct.
+    pub fn new<T: WgslType>(name: &'static str, offset: usize) -> Self {
+        let mut imports = vec![];
+        T::wgsl_imports(&mut imports);
+        Self {
+            name,
+            offset,
+            ty: T::wgsl_type(),
+            align: T::wgsl_align(),
+            size: T::wgsl_size(),
+            imports,
+        }
+    }
+}
+
+macro_rules! impl_scalar_type {
+    ($($ty:ty => $name:literal),*) => {
+        $(
+            impl WgslType for $ty {
+                fn wgsl_type() -> String {
+                    $name.to_owned()
+                }
+
+                fn wgsl_align() -> usize {
+                    4
+                }
+
+                fn wgsl_size() -> usize {
+                    4
+                }
+
+                fn is_scalar() -> bool {
+                    true
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar_type!(f32 => "f32", i32 => "i32", u32 => "u32");
+
+impl<T: WgslType, const N: usize> WgslType for [T; N] {
+    fn wgsl_type() -> String {
+        if is_vector::<T, N>() {
+            format!("vec{N}<{}>", T::wgsl_type())
+        } else {
+            format!("array<{}, {N}>", T::wgsl_type())
+        }
+    }
+
+    fn wgsl_align() -> usize {
+        // vec3 is aligned like vec4.
+        if is_vector::<T, N>() {
+            N.next_power_of_two() * T::wgsl_size()
+        } else {
+            T::wgsl_align()
+        }
+    }
+
+    fn wgsl_size() -> usize {
+        if is_vector::<T, N>() {
+            N * T::wgsl_size()
+        } else {
+            N * T::wgsl_size().next_multiple_of(T::wgsl_align())
+        }
+    }
+
+    fn wgsl_imports(imports: &mut Vec<String>) {
+        T::wgsl_imports(imports);
+    }
+}
+
+/// Whether `[T; N]` is a wgsl vector.
+fn is_vector<T: WgslType, const N: usize>() -> bool {
+    T::is_scalar() && (2..=4).contains(&N)
+}
+
+/// Alignment of the wgsl struct generated for `T`: the largest alignment of its members.
+pub fn struct_align<T: WgslStruct>() -> usize {
+    T::wgsl_fields()
+        .iter()
+        .map(|field| field.align)
+        .max()
+        .unwrap_or(1)
+}
+
+/// Size of the wgsl struct generated for `T`: its rust size, rounded up to its alignment.
+pub fn struct_size<T: WgslStruct>() -> usize {
+    std::mem::size_of::<T>().next_multiple_of(struct_align::<T>())
+}
+
+/// Import declaring `T`, such as `rust:PolygonSettings`.
+pub fn import_name<T: WgslStruct>() -> String {
+    format!("{RUST_IMPORT_PREFIX}{}", T::WGSL_NAME)
+}
+
+/// Generate the wgsl declaration of `T`, preceded by the imports of the structs it uses.
+///
+/// # Errors
+/// - The fields the wgsl alignment rules cannot place at their rust offset.
+pub fn wgsl_declaration<T: WgslStruct>() -> Result<String, Vec<String>> {
+    let fields = T::wgsl_fields();
+    // Padding fields are covered by the `@size` of the previous member.
+    let fields: Vec<_> = fields
+        .iter()
+        .filter(|field| !field.name.starts_with('_'))
+        .collect();
+
+    let mut errors = vec![];
+    match fields.first() {
+        None => errors.push("wgsl structs need at least one field".to_owned()),
+        Some(first) if first.offset != 0 => errors.push(format!(
+            "`{}` is at offset {}, wgsl structs cannot start with padding",
+            first.name, first.offset
+        )),
+        Some(_) => {}
+    }
+
+    let mut imports: Vec<String> = vec![];
+    let mut members = vec![];
+    for (index, field) in fields.iter().enumerate() {
+        let end = fields
+            .get(index + 1)
+            .map_or(std::mem::size_of::<T>(), |next| next.offset);
+        let span = end - field.offset;
+
+        if !field.offset.is_multiple_of(field.align) {
+            errors.push(format!(
+                "`{}` ({}) is at offset {}, wgsl aligns it to {} bytes",
+                field.name, field.ty, field.offset, field.align
+            ));
+        }
+        if span < field.size {
+            errors.push(format!(
+                "`{}` ({}) takes {} bytes in wgsl but only {span} bytes in rust",
+                field.name, field.ty, field.size
+            ));
+        }
+
+        for import in &field.imports {
+            if !imports.contains(import) {
+                imports.push(import.clone());
+            }
+        }
+        let size = if span > field.size {
+            format!("@size({span}) ")
+        } else {
+            String::new()
+        };
+        members.push(format!("    {size}{}: {},\n", field.name, field.ty));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut declaration = String::new();
+    for import in imports {
+        declaration += &format!("#import \"{import}\"\n");
+    }
+    declaration += &format!(
+        "// Generated from the rust struct `{}`.\nstruct {} {{\n",
+        T::WGSL_NAME,
+        T::WGSL_NAME
+    );
+    declaration.extend(members);
+    declaration += "};\n";
+    Ok(declaration)
+}
+
+/// Struct importable from shaders, registered by `#[derive(WgslStruct)]`.
+#[derive(Clone, Copy, Debug)]
+pub struct RustStruct {
+    pub name: &'static str,
+    pub declaration: fn() -> Result<String, Vec<String>>,
+}
+
+impl RustStruct {
+    pub const fn of<T: WgslStruct>() -> Self {
+        Self {
+            name: T::WGSL_NAME,
+            declaration: wgsl_declaration::<T>,
+        }
+    }
+}
+
+inventory::collect!(RustStruct);
+
+/// Load the declaration of a `rust:` import, or `None` if `import` is a file.
+///
+/// # Errors
+/// - [`PipelineError::ShaderNotFound`] if no struct deriving [`WgslStruct`] is named after the import.
+/// - [`PipelineError::StructLayoutMismatch`] if the struct has no wgsl equivalent.
+pub fn load_rust_struct(import: &str) -> Option<Result<String, PipelineError>> {
+    let name = import.strip_prefix(RUST_IMPORT_PREFIX)?;
+    let Some(rust_struct) = inventory::iter::<RustStruct>
+        .into_iter()
+        .find(|rust_struct| rust_struct.name == name)
+    else {
+        return Some(Err(PipelineError::ShaderNotFound {
+            file: import.to_owned(),
+        }));
+    };
+
+    Some(
+        (rust_struct.declaration)().map_err(|mismatches| PipelineError::StructLayoutMismatch {
+            shader: import.to_owned(),
+            rust_struct: name.to_owned(),
+            wgsl_struct: name.to_owned(),
+            mismatches,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo_pipelines::polygon::PolygonSettings;
+    use crate::struct_layout::{check_struct_layout, LayoutRules};
+    use crate::{CameraLookAt, ShaderBuilderForLibrary, ShaderDefines};
+
+    #[repr(C)]
+    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, WgslStruct)]
+    struct Padded {
+        elapsed: f32,
+        _padding: [f32; 3],
+        camera: CameraLookAt,
+        _camera_padding: f32,
+        color: [f32; 3],
+        counts: [u32; 5],
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, WgslStruct)]
+    struct Misaligned {
+        elapsed: f32,
+        size: [f32; 2],
+        camera: CameraLookAt,
+    }
+
+    #[test]
+    fn test_declaration() {
+        assert_eq!(
+            wgsl_declaration::<PolygonSettings>().unwrap(),
+            "// Generated from the rust struct `PolygonSettings`.\n\
+             struct PolygonSettings {\n    \
+                 elapsed: f32,\n    \
+                 polygon_size: f32,\n    \
+                 polygon_edge_count: u32,\n    \
+                 speed: f32,\n\
+             };\n"
+        );
+
+        // Padding fields are folded in the previous member, which keeps the rust offsets.
+        assert_eq!(
+            wgsl_declaration::<Padded>().unwrap(),
+            "#import \"rust:CameraLookAt\"\n\
+             // Generated from the rust struct `Padded`.\n\
+             struct Padded {\n    \
+                 @size(16) elapsed: f32,\n    \
+                 camera: CameraLookAt,\n    \
+                 color: vec3<f32>,\n    \
+                 counts: array<u32, 5>,\n\
+             };\n"
+        );
+    }
+
+    #[test]
+    fn test_declaration_errors() {
+        assert_eq!(
+            wgsl_declaration::<Misaligned>().unwrap_err(),
+            [
+                "`size` (vec2<f32>) is at offset 4, wgsl aligns it to 8 bytes",
+                "`camera` (CameraLookAt) is at offset 12, wgsl aligns it to 16 bytes",
+                "`camera` (CameraLookAt) takes 32 bytes in wgsl but only 28 bytes in rust",
+            ]
+        );
+
+        let result = load_rust_struct("rust:Unknown");
+        assert!(
+            matches!(result, Some(Err(PipelineError::ShaderNotFound { file })) if file == "rust:Unknown")
+        );
+        assert!(load_rust_struct("demos/polygon/draw.wgsl").is_none());
+    }
+
+    #[test]
+    fn test_import_rust_struct() {
+        // The generated declaration matches the rust layout once parsed by naga.
+        // Deriving `WgslStruct` registered `Padded`, even in a test module.
+        let load = |name: &str| match name {
+            "test.wgsl" => Ok("#import \"rust:Padded\"\n".to_owned()),
+            _ => load_rust_struct(name).unwrap(),
+        };
+        let shader = crate::shader_builder::compose_with(
+            &load,
+            &Default::default(),
+            "test.wgsl",
+            &ShaderDefines::new(),
+        )
+        .unwrap();
+        let module = shader.validate().unwrap();
+        check_struct_layout::<Padded>("test.wgsl", &module, "Padded", LayoutRules::Storage)
+            .unwrap();
+        assert!(shader.imports["rust:Padded"].contains(&"rust:CameraLookAt".to_owned()));
+
+        // Imported by the library shaders.
+        let shader =
+            ShaderBuilderForLibrary::compose("demos/polygon/draw.wgsl", &ShaderDefines::new())
+                .unwrap();
+        assert_eq!(
+            shader.imports["demos/polygon/draw.wgsl"],
+            ["rust:PolygonSettings", "shared:math.wgsl"]
+        );
+    }
+}
@@ -34,6 +34,20 @@ pub struct WinitInputHelper {
     step_duration: Option<Duration>,
 }
 
+// Created by the runner and read by the library, see `layout_fingerprint` in `lib.rs`.
+crate::impl_type_layout!(WinitInputHelper {
+    current,
+    dropped_file,
+    window_resized,
+    window_size,
+    scale_factor_changed,
+    scale_factor,
+    destroyed,
+    close_requested,
+    step_start,
+    step_duration,
+});
+
 impl Default for WinitInputHelper {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,352 @@
+//! Validate the shaders of a folder without starting the app,
+//! using the same preprocessor as the library and naga.
+//!
+//! ```text
+//! cargo run -p shader-check -- [--root shaders] [-D NAME[=VALUE]]... [--dump] [--imports] [FILES]...
+//! ```
+//!
+//! Without files, every shader with an entry point (`@vertex`, `@fragment` or `@compute`) is checked,
+//! along with glsl (`.vert`, `.frag`, `.comp`) and SPIR-V (`.spv`) files.
+//! Exits with 1 if a shader is invalid, and 2 on invalid arguments.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use lib::composed_shader::{ComposedShader, ShaderLanguage};
+use lib::pipeline::{PipelineError, ShaderDiagnostic, ShaderLocation};
+use lib::shader_builder::{compose_with, load_prefixed, prefixed_files, shader_text, ImportPaths};
+use lib::wgsl_struct::load_rust_struct;
+use lib::{ShaderDefValue, ShaderDefines};
+
+const USAGE: &str = "\
+Usage: shader-check [OPTIONS] [FILES]...
+
+Preprocess and validate shaders with naga.
+FILES are relative to the shader root, every shader with an entry point is checked by default.
+
+Options:
+  --root <DIR>       Shader root folder [default: shaders]
+  -D <NAME[=VALUE]>  Define a value for #ifdef and wgsl constants (#define in glsl), eg. -D COUNT=64u
+  --dump             Print the flattened shader
+  --imports          Print the import graph
+  -h, --help         Print this help";
+
+/// Command line arguments.
+struct Args {
+    root: PathBuf,
+    defines: ShaderDefines,
+    dump: bool,
+    imports: bool,
+    files: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let shaders = match find_shaders(&args.root) {
+        Ok(shaders) => shaders,
+        Err(error) => {
+            eprintln!("error: could not read {}: {error}", args.root.display());
+            return ExitCode::from(2);
+        }
+    };
+    let load = |name: &str| load_shader(&args.root, name);
+    // Modules are only looked for when a shader imports one.
+    let module_files: Vec<String> = shaders.iter().cloned().chain(prefixed_files()).collect();
+    let import_paths = ImportPaths::new(move || module_files.clone());
+
+    let files = if args.files.is_empty() {
+        // Only check entry points: other files are only valid once imported.
+        // Glsl and SPIR-V files have a single stage given by their extension.
+        shaders
+            .into_iter()
+            .filter(|file| {
+                if ShaderLanguage::from_file(file) != ShaderLanguage::Wgsl {
+                    return true;
+                }
+                let source = load(file).unwrap_or_default();
+                ["@vertex", "@fragment", "@compute"]
+                    .iter()
+                    .any(|stage| source.contains(stage))
+            })
+            .collect()
+    } else {
+        args.files.clone()
+    };
+
+    let mut failures = 0;
+    for file in &files {
+        let shader = match ShaderLanguage::from_file(file) {
+            ShaderLanguage::SpirV => std::fs::read(args.root.join(file))
+                .map(|binary| ComposedShader::from_spirv(file, binary))
+                .map_err(|_| PipelineError::ShaderNotFound {
+                    file: file.to_owned(),
+                }),
+            _ => compose_with(&load, &import_paths, file, &args.defines),
+        };
+        let result = shader.and_then(|shader| {
+            if args.imports {
+                print_imports(&shader, file);
+            }
+            if args.dump {
+                print!("{}", shader.code);
+            }
+            shader.validate().map_err(PipelineError::ShaderParseError)
+        });
+
+        match result {
+            Ok(_) => println!("{file}: ok"),
+            Err(error) => {
+                failures += 1;
+                print_error(&args.root, &error);
+            }
+        }
+    }
+
+    println!("{} shader(s) checked, {failures} error(s)", files.len());
+    if failures > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Parse the command line, returns `None` when help is requested.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut parsed = Args {
+        root: PathBuf::from("shaders"),
+        defines: ShaderDefines::new(),
+        dump: false,
+        imports: false,
+        files: vec![],
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--root" => {
+                parsed.root = args.next().ok_or("--root expects a folder")?.into();
+            }
+            "--dump" => parsed.dump = true,
+            "--imports" => parsed.imports = true,
+            "-D" => {
+                let define = args.next().ok_or("-D expects NAME[=VALUE]")?;
+                let (name, value) = parse_define(&define)?;
+                parsed.defines.insert(name, value);
+            }
+            _ if arg.starts_with("-D") => {
+                let (name, value) = parse_define(&arg[2..])?;
+                parsed.defines.insert(name, value);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => parsed.files.push(arg),
+        }
+    }
+
+    Ok(Some(parsed))
+}
+
+/// Parse `NAME[=VALUE]`, the type of the value being inferred like in wgsl:
+/// `true`/`false`, `64u`, `-3` or `-3i`, `0.5`, anything else being a type alias.
+/// An empty value is an error, it would declare an alias to nothing.
+fn parse_define(define: &str) -> Result<(String, ShaderDefValue), String> {
+    let Some((name, value)) = define.split_once('=') else {
+        return Ok((define.to_owned(), ShaderDefValue::Bool(true)));
+    };
+    if value.is_empty() {
+        return Err(format!("-D {name}= expects a value"));
+    }
+
+    let value = if let Ok(value) = value.parse::<bool>() {
+        ShaderDefValue::Bool(value)
+    } else if let Some(value) = value.strip_suffix('u').and_then(|v| v.parse().ok()) {
+        ShaderDefValue::UInt(value)
+    } else if let Ok(value) = value.strip_suffix('i').unwrap_or(value).parse() {
+        ShaderDefValue::Int(value)
+    } else if let Ok(value) = value.strip_suffix('f').unwrap_or(value).parse() {
+        ShaderDefValue::Float(value)
+    } else {
+        ShaderDefValue::Type(value.to_owned())
+    };
+    Ok((name.to_owned(), value))
+}
+
+/// Load a shader file relative to `root`, with the same errors as the embedded shaders.
+/// `rust:` imports are generated from the library structs,
+/// and prefixed imports are loaded from the library shader roots.
+fn load_shader(root: &Path, name: &str) -> Result<String, PipelineError> {
+    if let Some(declaration) = load_rust_struct(name) {
+        return declaration;
+    }
+    if let Some(file) = load_prefixed(name) {
+        return file.and_then(|file| shader_text(name, &file));
+    }
+    let bytes = std::fs::read(root.join(name)).map_err(|_| PipelineError::ShaderNotFound {
+        file: name.to_owned(),
+    })?;
+    shader_text(name, &bytes)
+}
+
+/// Find all shader files of `root`, relative to it and sorted.
+/// Glsl files without a stage extension are left out: they are only included.
+fn find_shaders(root: &Path) -> std::io::Result<Vec<String>> {
+    let mut files = vec![];
+    let mut folders = vec![root.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        for entry in std::fs::read_dir(folder)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                folders.push(path);
+            } else if path.extension().is_some_and(|extension| {
+                ShaderLanguage::from_extension(&extension.to_string_lossy()).is_some()
+            }) {
+                let name = path.strip_prefix(root).unwrap_or(&path);
+                // Imports always use forward slashes.
+                files.push(name.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Print the files imported by `file`, indented by depth.
+/// Files imported several times are only expanded the first time.
+fn print_imports(shader: &ComposedShader, file: &str) {
+    fn print_file(shader: &ComposedShader, file: &str, depth: usize, printed: &mut Vec<String>) {
+        let expanded = printed.iter().any(|printed| printed == file);
+        println!(
+            "{}{file}{}",
+            "  ".repeat(depth),
+            if expanded { " (already imported)" } else { "" }
+        );
+        if expanded {
+            return;
+        }
+        printed.push(file.to_owned());
+        for import in shader.imports.get(file).into_iter().flatten() {
+            print_file(shader, import, depth + 1, printed);
+        }
+    }
+
+    // The root file is stored normalized, like imports.
+    let root = shader
+        .imports
+        .keys()
+        .find(|name| name.as_str() == file.trim_start_matches('/'));
+    print_file(shader, root.map_or(file, String::as_str), 0, &mut vec![]);
+}
+
+/// Print an error like rustc does, with paths relative to the working directory
+/// so they can be clicked in a terminal.
+fn print_error(root: &Path, error: &PipelineError) {
+    let path = |location: &ShaderLocation| {
+        format!(
+            "{}:{}:{}",
+            root.join(&location.file).display(),
+            location.line,
+            location.column
+        )
+    };
+
+    match error {
+        PipelineError::ShaderParseError(diagnostic) => print_diagnostic(diagnostic, path),
+        _ => {
+            eprintln!("error: {}", error.to_string().trim_end());
+            if let Some(location) = error.location() {
+                eprintln!("  --> {}", path(location));
+            }
+        }
+    }
+    eprintln!();
+}
+
+/// Print a naga diagnostic with each of its labels under the line it points to.
+fn print_diagnostic(diagnostic: &ShaderDiagnostic, path: impl Fn(&ShaderLocation) -> String) {
+    eprint!("error: {}", diagnostic.message);
+    for cause in &diagnostic.chain {
+        eprint!(": {cause}");
+    }
+    eprintln!();
+
+    let width = diagnostic
+        .labels
+        .iter()
+        .map(|label| label.location.line.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let gutter = " ".repeat(width);
+    for label in &diagnostic.labels {
+        let location = &label.location;
+        eprintln!("{gutter}--> {}", path(location));
+        eprintln!("{gutter} |");
+        eprintln!("{:>width$} | {}", location.line, label.snippet);
+        eprintln!(
+            "{gutter} | {} {}",
+            location.marker(&label.snippet),
+            label.message
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_define() {
+        let args = parse(&[
+            "-D",
+            "N",
+            "-DCOUNT=64u",
+            "-D",
+            "X=-3",
+            "-D",
+            "Y=0.5",
+            "-D",
+            "T=f32",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(args.defines["N"], ShaderDefValue::Bool(true));
+        assert_eq!(args.defines["COUNT"], ShaderDefValue::UInt(64));
+        assert_eq!(args.defines["X"], ShaderDefValue::Int(-3));
+        assert_eq!(args.defines["Y"], ShaderDefValue::Float(0.5));
+        assert_eq!(args.defines["T"], ShaderDefValue::Type("f32".to_owned()));
+
+        assert_eq!(parse_define("X="), Err("-D X= expects a value".to_owned()));
+        assert!(parse(&["-D", "X="]).is_err());
+        assert!(parse(&["-D"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["--root", "demos", "--dump", "draw.wgsl"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.root, PathBuf::from("demos"));
+        assert!(args.dump && !args.imports);
+        assert_eq!(args.files, ["draw.wgsl"]);
+
+        assert!(parse(&["--help"]).unwrap().is_none());
+        assert_eq!(parse(&["--root"]).err().unwrap(), "--root expects a folder");
+        assert_eq!(
+            parse(&["--verbose"]).err().unwrap(),
+            "unknown option --verbose"
+        );
+    }
+}
@@ -1,28 +1,25 @@
+// NUM_PARTICLES and PARTICLES_PER_GROUP are injected from rust, see boids.rs.
+
 struct Particle {
   pos: vec2<f32>,
   vel: vec2<f32>,
 };
 
-struct SimParams {
-  deltaT: f32,
-  rule1Distance: f32,
-  rule2Distance: f32,
-  rule3Distance: f32,
-  rule1Scale: f32,
-  rule2Scale: f32,
-  rule3Scale: f32,
-  speed: f32,
-};
+// Generated from `BoidsSettings` in boids.rs.
+#import "rust:BoidsSettings"
 
-@group(0) @binding(0) var<uniform> params : SimParams;
+@group(0) @binding(0) var<uniform> params : BoidsSettings;
 @group(0) @binding(1) var<storage, read> particlesSrc : array<Particle>;
 @group(0) @binding(2) var<storage, read_write> particlesDst : array<Particle>;
 
+// Set when creating the pipeline, edited in the ui.
+override max_velocity: f32 = 0.1;
+
 // https://github.com/austinEng/Project6-Vulkan-Flocking/blob/master/data/shaders/computeparticles/particle.comp
 @compute
-@workgroup_size(64)
+@workgroup_size(PARTICLES_PER_GROUP)
 fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
-    let total = arrayLength(&particlesSrc);
+    let total = NUM_PARTICLES;
     let index = global_invocation_id.x;
     if index >= total {
         return;
@@ -49,14 +46,14 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
         let pos = particlesSrc[i].pos;
         let vel = particlesSrc[i].vel;
 
-        if distance(pos, vPos) < params.rule1Distance {
+        if distance(pos, vPos) < params.rule1_distance {
             cMass += pos;
             cMassCount += 1;
         }
-        if distance(pos, vPos) < params.rule2Distance {
+        if distance(pos, vPos) < params.rule2_distance {
             colVel -= pos - vPos;
         }
-        if distance(pos, vPos) < params.rule3Distance {
+        if distance(pos, vPos) < params.rule3_distance {
             cVel += vel;
             cVelCount += 1;
         }
@@ -72,13 +69,13 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
         cVel *= 1.0 / f32(cVelCount);
     }
 
-    vVel = vVel + (cMass * params.rule1Scale) + (colVel * params.rule2Scale) + (cVel * params.rule3Scale);
+    vVel = vVel + (cMass * params.rule1_scale) + (colVel * params.rule2_scale) + (cVel * params.rule3_scale);
 
   // clamp velocity for a more pleasing simulation
-    vVel = normalize(vVel) * clamp(length(vVel), 0.0, 0.1);
+    vVel = normalize(vVel) * clamp(length(vVel), 0.0, max_velocity);
 
   // kinematic update
-    vPos += vVel * params.deltaT * params.speed;
+    vPos += vVel * params.delta_t * params.speed;
 
   // Wrap around boundary
     if vPos.x < -1.0 {
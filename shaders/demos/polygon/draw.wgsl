@@ -1,12 +1,9 @@
-struct Uniforms {
-  elapsed: f32,
-  size: f32,
-  edge_count: u32,
-  _padding: f32,  // padding to 16 bytes, required for WebGL.
-};
+// Generated from `PolygonSettings` in polygon.rs.
+#import "rust:PolygonSettings"
+#import "shared:math.wgsl"
 
 @group(0) @binding(0)
-var<uniform> uniforms: Uniforms;
+var<uniform> uniforms: PolygonSettings;
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
@@ -19,8 +16,6 @@ struct VertexOutput {
 fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
     var out: VertexOutput;
 
-    let pi = 3.14159265359;
-
     // To draw a regular polygon with n edges
     // we draw n triangles with the center of the polygon and two adjacent vertices.
     // calling 0 the center and 1234... the vertices of the polygon
@@ -35,12 +30,12 @@ fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
     // 012 123 234 345 ... (triangle_id + vertex_offset) 
     let vertex_id = triangle_id + vertex_offset;
     // finally we will just have to consider the first element of the triangle as the center later.
-    let vertex_angle = 2.0 * pi * f32(vertex_id) / f32(uniforms.edge_count);
+    let vertex_angle = TAU * f32(vertex_id) / f32(uniforms.polygon_edge_count);
 
     if vertex_offset > 0u {
         // polygon edge.
         let angle = uniforms.elapsed + vertex_angle;
-        let radius = uniforms.size;
+        let radius = uniforms.polygon_size;
         out.position = vec4<f32>(radius * cos(angle), radius * sin(angle), 0.0, 1.0);
     } else {
         // no offset: center of the polygon.
@@ -59,8 +54,16 @@ fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
 }
 
 
+// Set when creating the pipeline, edited in the ui.
+override brightness: f32 = 1.0;
+override grayscale: bool = false;
+
 /// Fragment entry point.
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return in.color;
+    var color = in.color.rgb * brightness;
+    if grayscale {
+        color = vec3<f32>(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+    }
+    return vec4<f32>(color, in.color.a);
 }
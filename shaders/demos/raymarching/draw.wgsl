@@ -1,7 +1,10 @@
-#import "demos/raymarching/common.wgsl"
+#import "./common.wgsl"
 
-#import "demos/raymarching/draw_2d.wgsl"
-#import "demos/raymarching/draw_3d.wgsl"
+#ifdef RAYMARCHING_2D
+#import "./draw_2d.wgsl"
+#else
+#import "./draw_3d.wgsl"
+#endif
 
 @vertex
 fn vs_main(
@@ -14,10 +17,11 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // let color = sdf_2d(in.clip_position.xy);
-
-    let xy = in.clip_position.xy / vec2<f32>(uniforms.width, uniforms.height);
+#ifdef RAYMARCHING_2D
+    let color = sdf_2d(in.clip_position.xy);
+#else
     let color = sdf_3d(in.clip_position.xy);
+#endif
     return color;
 }
 
@@ -1,4 +1,4 @@
-#import "demos/raymarching/camera.wgsl"
+#import "./camera.wgsl"
 
 const EPSILON = 0.001;
 
@@ -0,0 +1,15 @@
+// Shared by the glsl test shaders, included with `#include "test_glsl/common.glsl"`.
+#ifndef COMMON_GLSL
+#define COMMON_GLSL
+
+struct Particle {
+    vec2 pos;
+    vec2 vel;
+};
+
+// Wrap a position around the [-1, 1] boundary.
+vec2 wrap(vec2 position) {
+    return mod(position + 1.0, 2.0) - 1.0;
+}
+
+#endif
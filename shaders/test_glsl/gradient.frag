@@ -0,0 +1,13 @@
+#version 450
+// Animated gradient over the fullscreen triangle of `fullscreen.vert`.
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 color;
+
+layout(set = 0, binding = 0) uniform Settings {
+    float elapsed;
+};
+
+void main() {
+    color = vec4(uv, 0.5 + 0.5 * sin(elapsed), 1.0);
+}
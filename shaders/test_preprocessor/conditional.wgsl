@@ -0,0 +1,11 @@
+// Test shader to check that conditional blocks are resolved as expected.
+#ifdef OUTER
+const outer_defined: bool = true;
+#ifndef INNER
+const inner_undefined: bool = true;
+#else
+const inner_defined: bool = true;
+#endif
+#else
+const outer_undefined: bool = true;
+#endif
@@ -0,0 +1,4 @@
+// Test shader importing a file that imports it back.
+#import "./cycle_b.wgsl"
+
+const a: f32 = 1.0;
@@ -0,0 +1,4 @@
+// Test shader importing the file that imports it.
+#import "./cycle_a.wgsl"
+
+const b: f32 = 2.0;
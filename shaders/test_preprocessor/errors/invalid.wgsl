@@ -0,0 +1,7 @@
+// Test shader whose error is located in an imported file.
+#import "test_preprocessor/common.wgsl"
+#import "test_preprocessor/errors/invalid_import.wgsl"
+
+fn main_value() -> f32 {
+    return value();
+}
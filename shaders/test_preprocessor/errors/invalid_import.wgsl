@@ -0,0 +1,4 @@
+// Imported file with an unknown identifier.
+fn value() -> f32 {
+    return uniforms.elapsed * unknown_scale;
+}
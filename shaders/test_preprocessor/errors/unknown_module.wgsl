@@ -0,0 +1,2 @@
+// Module that no shader declares.
+#import test_preprocessor::unknown
@@ -0,0 +1,8 @@
+// Import items of a module, used unqualified.
+#import test_preprocessor::noise::{fbm, hash}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let value = fbm(position.xy) * hash(position.yx);
+    return vec4<f32>(value, value, value, 1.0);
+}
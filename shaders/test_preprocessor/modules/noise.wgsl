@@ -0,0 +1,18 @@
+// Module imported by its declared path rather than its file.
+#define_import_path test_preprocessor::noise
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(127.1, 311.7))) * 43758.5453);
+}
+
+fn fbm(p: vec2<f32>) -> f32 {
+    var value = 0.0;
+    var amplitude = 0.5;
+    var position = p;
+    for (var i = 0; i < 4; i++) {
+        value += amplitude * hash(floor(position));
+        position *= 2.0;
+        amplitude *= 0.5;
+    }
+    return value;
+}
@@ -0,0 +1,8 @@
+// Import a module, its items being qualified by the module name or its full path.
+#import test_preprocessor::noise
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let value = noise::fbm(position.xy) * test_preprocessor::noise::hash(position.yx);
+    return vec4<f32>(value, value, value, 1.0);
+}
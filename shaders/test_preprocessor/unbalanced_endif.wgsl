@@ -0,0 +1,3 @@
+// Test shader with an #endif that does not close any block.
+const value: f32 = 1.0;
+#endif
@@ -0,0 +1,4 @@
+// Shared shader library, imported with `#import "shared:math.wgsl"`.
+
+const PI: f32 = 3.14159265359;
+const TAU: f32 = 6.28318530718;
@@ -0,0 +1,103 @@
+use lib::pipeline::{PipelineError, ShaderLocation};
+
+/// Overlay displaying the reload errors on top of the pipeline.
+/// Lives in the binary so it is still displayed when the library is broken.
+#[derive(Default)]
+pub struct ErrorOverlay {
+    /// Last error of each shader file, empty once dismissed or after a successful reload.
+    errors: Vec<PipelineError>,
+}
+
+impl ErrorOverlay {
+    /// Display a new error, replacing the previous one of the same file.
+    pub fn set(&mut self, error: PipelineError) {
+        self.errors
+            .retain(|previous| previous.file() != error.file());
+        self.errors.push(error);
+    }
+
+    /// Hide the overlay, called after the pipeline was created.
+    pub fn clear(&mut self) {
+        self.errors.clear();
+    }
+
+    /// Hide the errors of the shader files that were rebuilt successfully,
+    /// keeping the ones of the shaders that are still broken.
+    /// Errors that are not tied to a file cannot be told apart and are cleared too.
+    pub fn clear_files(&mut self, files: &[String]) {
+        self.errors.retain(|error| {
+            error
+                .file()
+                .is_some_and(|file| !files.iter().any(|f| f == file))
+        });
+    }
+
+    /// Draw the overlay if there is an error to display.
+    pub fn show(&mut self, context: &egui::Context) {
+        if self.errors.is_empty() {
+            return;
+        }
+
+        let mut open = true;
+        let mut dismissed = false;
+        egui::Window::new("Reload error")
+            .open(&mut open)
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+            .collapsible(false)
+            .resizable(false)
+            .show(context, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for (index, error) in self.errors.iter().enumerate() {
+                            if index > 0 {
+                                ui.separator();
+                            }
+                            show_error(ui, error);
+                        }
+                    });
+
+                ui.separator();
+                dismissed = ui.button("Dismiss").clicked();
+            });
+
+        if !open || dismissed {
+            self.errors.clear();
+        }
+    }
+}
+
+/// Draw an error, with the line of code it points to if it has a location.
+fn show_error(ui: &mut egui::Ui, error: &PipelineError) {
+    if let Some(location) = error.location() {
+        ui.label(egui::RichText::new(location.to_string()).strong());
+    }
+
+    match error {
+        PipelineError::ShaderParseError(diagnostic) => {
+            let message = std::iter::once(&diagnostic.message)
+                .chain(&diagnostic.chain)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(": ");
+            ui.colored_label(ui.visuals().error_fg_color, message);
+
+            for label in &diagnostic.labels {
+                ui.separator();
+                ui.label(format!("{}: {}", label.location, label.message));
+                show_snippet(ui, &label.location, &label.snippet);
+            }
+        }
+        _ => {
+            ui.colored_label(ui.visuals().error_fg_color, error.to_string().trim_end());
+            if let (Some(location), Some(snippet)) = (error.location(), error.snippet()) {
+                show_snippet(ui, location, snippet);
+            }
+        }
+    }
+}
+
+/// Draw the offending line, underlining the span of `location` below it.
+fn show_snippet(ui: &mut egui::Ui, location: &ShaderLocation, snippet: &str) {
+    ui.monospace(format!("{snippet}\n{}", location.marker(snippet)));
+}
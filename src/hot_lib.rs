@@ -3,11 +3,13 @@
 #[hot_lib_reloader::hot_module(dylib = "lib")]
 pub mod library_bridge {
     // Any type that is used in the functions signatures in lib.rs should be imported here.
-    pub use lib::pipeline::{PipelineError, PipelineFuncs};
+    pub use lib::pipeline::{PipelineError, PipelineFuncs, ShaderChanges};
     pub use lib::reload_flags::{LibState, ReloadFlags};
     use lib::winit_input_helper;
     pub use lib::CameraLookAt;
-    pub use lib::CurrentPipeline;
+    pub use lib::LayoutFingerprint;
+    pub use lib::PipelineHandle;
+    pub use lib::PipelineInfo;
 
     // Specific hot reload helpers.
     hot_functions_from_file!("lib/src/lib.rs");
@@ -26,7 +28,7 @@ pub mod library_bridge {
 pub mod library_bridge {
     // pub use lib::pipeline::{PipelineFuncs, PipelineError};
     pub use lib::reload_flags::{LibState, ReloadFlags};
-    // pub use lib::CurrentPipeline;
+    // pub use lib::PipelineHandle;
 
     // Include lib file directly since it is not done via the hot-reload module.
     pub use lib::*;
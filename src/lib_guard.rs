@@ -0,0 +1,138 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+
+/// Location of the last panic, recorded by the hook set in [`install_panic_hook`].
+static PANIC_LOCATION: Mutex<Option<String>> = Mutex::new(None);
+
+/// Record the location of panics, before printing them with the previous hook.
+/// `catch_unwind` only gives the panic message.
+pub fn install_panic_hook() {
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Ok(mut location) = PANIC_LOCATION.lock() {
+            *location = info.location().map(ToString::to_string);
+        }
+        previous_hook(info);
+    }));
+}
+
+/// Panic caught in a call to the library.
+pub struct LibPanic {
+    /// Library function that panicked.
+    pub function: &'static str,
+    pub message: String,
+    /// `file:line:column` of the panic, if the hook recorded it.
+    pub location: Option<String>,
+}
+
+/// Guard around the calls to the hot-reloaded library, so a panic in `lib` does not end the session.
+/// Lives in the binary like the error overlay.
+///
+/// After a panic the pipeline may be in an inconsistent state, so the library is frozen:
+/// no function is called until it is reloaded.
+/// Panics only unwind in native builds, wasm aborts.
+///
+/// A reload changing the layout of the types the runner uses itself cannot be recovered from:
+/// the library is frozen until the app is restarted.
+#[derive(Default)]
+pub struct LibGuard {
+    panic: Option<LibPanic>,
+    /// Why the app must be restarted.
+    restart_required: Option<String>,
+}
+
+impl LibGuard {
+    /// Call the library `function`, unless it is frozen.
+    /// Return `None` if the library is frozen or if the call panicked, freezing it.
+    pub fn call<R>(&mut self, function: &'static str, call: impl FnOnce() -> R) -> Option<R> {
+        if self.is_frozen() {
+            return None;
+        }
+
+        match panic::catch_unwind(AssertUnwindSafe(call)) {
+            Ok(result) => Some(result),
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| (*message).to_owned())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "panic with a non-string payload".to_owned());
+                let location = PANIC_LOCATION
+                    .lock()
+                    .ok()
+                    .and_then(|mut location| location.take());
+                log::error!("library panicked in {function}, frozen until it is reloaded");
+                self.panic = Some(LibPanic {
+                    function,
+                    message,
+                    location,
+                });
+                None
+            }
+        }
+    }
+
+    /// Let the library free a value it created, even if a panic froze it since:
+    /// nothing else would free it before the reload.
+    /// Skipped once a restart is required, the library may not know the layout of the value anymore.
+    pub fn free(&self, function: &'static str, free: impl FnOnce()) {
+        if self.restart_required.is_some() {
+            return;
+        }
+        if panic::catch_unwind(AssertUnwindSafe(free)).is_err() {
+            log::error!("library panicked in {function}, leaking the value");
+        }
+    }
+
+    /// Whether a call panicked since the last reload, or the app must be restarted.
+    pub fn is_frozen(&self) -> bool {
+        self.panic.is_some() || self.restart_required.is_some()
+    }
+
+    /// Stop calling the library for good.
+    pub fn require_restart(&mut self, reason: String) {
+        log::error!("{reason}, restart required");
+        self.restart_required = Some(reason);
+    }
+
+    /// Call the library again after it was reloaded, unless a restart is required.
+    pub fn resume(&mut self) {
+        if self.panic.take().is_some() {
+            log::info!("library reloaded, resuming");
+        }
+    }
+
+    /// Draw the panic or the restart message if the library is frozen.
+    pub fn show(&self, context: &egui::Context) {
+        if let Some(reason) = &self.restart_required {
+            egui::Window::new("Restart required")
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .collapsible(false)
+                .resizable(false)
+                .show(context, |ui| {
+                    ui.colored_label(ui.visuals().error_fg_color, reason);
+                    ui.separator();
+                    ui.label("The runner cannot use the reloaded library, restart the app.");
+                });
+            return;
+        }
+
+        let Some(panic) = &self.panic else {
+            return;
+        };
+
+        egui::Window::new("Library panic")
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .collapsible(false)
+            .resizable(false)
+            .show(context, |ui| {
+                ui.label(egui::RichText::new(format!("in {}", panic.function)).strong());
+                if let Some(location) = &panic.location {
+                    ui.label(egui::RichText::new(location).strong());
+                }
+                ui.colored_label(ui.visuals().error_fg_color, &panic.message);
+                ui.separator();
+                ui.label("The pipeline is frozen until the library is reloaded.");
+            });
+    }
+}
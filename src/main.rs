@@ -10,8 +10,12 @@
 //! See [`lib::pipeline::PipelineFuncs`] for the trait to implement
 //! and `lib::demo_pipelines::polygon` for an example.
 
+mod error_overlay;
 mod hot_lib;
+mod lib_guard;
 mod runner;
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+mod shader_watcher;
 
 use std::sync::{Arc, Mutex};
 
@@ -21,44 +25,96 @@ use crate::hot_lib::library_bridge;
 #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 
-#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
-use std::path::Path;
-
-/// Watch shader folder. Only done in native debug mode.
+/// Watch shader folders. Only done in native debug mode.
 /// Everytime a shader is modified/added/deleted,
 /// it will update the `ReloadFlags` so the pipeline can reload them.
+/// Events are filtered and batched by an [`shader_watcher::EventCoalescer`].
 #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
-fn watch<P: AsRef<Path>>(
-    path: P,
+fn watch(
+    roots: Vec<shader_watcher::WatchedRoot>,
+    settings: shader_watcher::WatchSettings,
     data: Arc<Mutex<lib::reload_flags::ReloadFlags>>,
 ) -> notify::Result<()> {
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::time::{Duration, Instant};
+
     let (tx, rx) = std::sync::mpsc::channel();
 
     // Automatically select the best implementation for your platform.
     // You can also access each implementation directly e.g. INotifyWatcher.
     let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
 
-    // Add a path to be watched. All files and directories at that path and
+    // Add the paths to be watched. All files and directories at these paths and
     // below will be monitored for changes.
-    watcher.watch(path.as_ref(), RecursiveMode::Recursive)?;
+    let mut watched = vec![];
+    for root in roots {
+        if let Err(error) = watcher.watch(&root.folder, RecursiveMode::Recursive) {
+            log::error!("Could not watch {}: {error:?}", root.folder.display());
+            continue;
+        }
+        log::info!("Watching {}", root.folder.display());
+        // Events paths can be absolute depending on the platform.
+        let canonical = shader_watcher::WatchedRoot {
+            prefix: root.prefix,
+            folder: root.folder.canonicalize()?,
+        };
+        watched.extend([canonical, root]);
+    }
 
-    for res in rx {
-        match res {
-            Ok(event) => {
-                log::info!("Change: {:?}", event.paths);
-                let mut data = data.lock().unwrap();
-                event.paths.iter().for_each(|p| {
-                    let shader_path = p.to_str().unwrap().to_owned();
-                    data.shaders.push(shader_path);
-                });
+    let mut coalescer = shader_watcher::EventCoalescer::new(settings);
+    loop {
+        // Wait for the next event, or until the pending changes can be sent.
+        let timeout = coalescer.timeout(Instant::now()).unwrap_or(Duration::MAX);
+        match rx.recv_timeout(timeout) {
+            Ok(Ok(event)) => {
+                // Store paths relative to their shader folder, like shader names.
+                // Folders and files outside of the watched folders are left out.
+                let paths: Vec<_> = event
+                    .paths
+                    .iter()
+                    .filter(|p| !p.is_dir())
+                    .filter_map(|p| watched.iter().find_map(|root| root.shader_name(p)))
+                    .collect();
+                coalescer.push(paths.iter().map(String::as_str), Instant::now());
+            }
+            Ok(Err(error)) => log::error!("Error: {error:?}"),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if let Some(shaders) = coalescer.flush(Instant::now()) {
+            log::info!("Change: {shaders:?}");
+            let mut data = data.lock().unwrap();
+            for shader in shaders {
+                if !data.shaders.contains(&shader) {
+                    data.shaders.push(shader);
+                }
             }
-            Err(error) => log::error!("Error: {error:?}"),
         }
     }
 
     Ok(())
 }
 
+/// Wait until the runner released the pipeline before a library reload,
+/// which it does on its next event by setting the state to `Reloading`.
+/// The reload is blocked until then: the runner must not hold a pipeline
+/// built by the previous library once the new one is loaded.
+#[cfg(feature = "reload")]
+fn wait_for_pipeline_release(data: &Mutex<lib::reload_flags::ReloadFlags>) {
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let mut warned = false;
+    while data.lock().unwrap().lib == lib::reload_flags::LibState::AboutToReload {
+        if !warned && start.elapsed() > Duration::from_secs(1) {
+            log::warn!("waiting for the pipeline to be released before reloading the library");
+            warned = true;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
 /// App entry point.
 fn main() {
     let data = Arc::new(Mutex::new(lib::reload_flags::ReloadFlags {
@@ -68,14 +124,19 @@ fn main() {
 
     #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
     {
-        // Watch shaders folder.
+        // Watch shaders folders.
         // When a shader is saved, the pipeline will be recreated.
-        let path = "shaders";
-        log::info!("Watching {path}");
+        let root = shader_watcher::shader_root(
+            std::env::args().skip(1),
+            std::env::var_os(lib::shader_builder::SHADER_ROOT_VAR),
+        );
+        // Read by the library when loading `LibraryShaders`, even after it is reloaded.
+        std::env::set_var(lib::shader_builder::SHADER_ROOT_VAR, &root);
+        let roots = shader_watcher::watched_roots(root);
         let data = data.clone();
         std::thread::spawn(move || {
-            if let Err(error) = watch(path, data) {
-                log::error!("Could not watch shaders folder: {error:?}");
+            if let Err(error) = watch(roots, shader_watcher::WatchSettings::default(), data) {
+                log::error!("Could not watch shaders folders: {error:?}");
             }
         });
     }
@@ -87,10 +148,11 @@ fn main() {
         std::thread::spawn(move || loop {
             // wait until a reload is ready.
             {
-                library_bridge::subscribe().wait_for_about_to_reload();
-                // update lib state to reloading.
-                let mut data = data.lock().unwrap();
-                data.lib = lib::reload_flags::LibState::Reloading;
+                // The reload is blocked until the token is dropped.
+                let _blocker = library_bridge::subscribe().wait_for_about_to_reload();
+                // ask the runner to save the pipeline state and drop it.
+                data.lock().unwrap().lib = lib::reload_flags::LibState::AboutToReload;
+                wait_for_pipeline_release(&data);
             }
 
             // allow reload.
@@ -1,4 +1,5 @@
 use egui_wgpu::{Renderer, ScreenDescriptor};
+use lib::pipeline::{PipelineError, ShaderChanges};
 use lib::winit_input_helper::WinitInputHelper;
 use std::sync::{Arc, Mutex};
 use winit::event::StartCause;
@@ -9,7 +10,9 @@ use winit::{
     window::Window,
 };
 
+use crate::error_overlay::ErrorOverlay;
 use crate::hot_lib::library_bridge;
+use crate::lib_guard::LibGuard;
 
 struct EventLoopWrapper {
     event_loop: EventLoop<()>,
@@ -224,41 +227,44 @@ impl WgpuContext {
         let adapter_info = adapter.get_info();
         log::info!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
 
-        let optional_features = library_bridge::pipeline_optional_features();
-        let required_features = library_bridge::pipeline_required_features();
+        // Request what every pipeline the adapter supports needs, so they can all be switched to
+        // on the same device. Pipelines needing more are greyed out in the pipeline menu.
+        // If none is supported, the device gets the baseline limits and the reasons are displayed.
         let adapter_features = adapter.features();
-        assert!(
-            adapter_features.contains(required_features),
-            "Adapter does not support required features for this example: {:?}",
-            required_features - adapter_features
-        );
-
-        let required_downlevel_capabilities =
-            library_bridge::pipeline_required_downlevel_capabilities();
         let downlevel_capabilities = adapter.get_downlevel_capabilities();
-        assert!(
-            downlevel_capabilities.shader_model >= required_downlevel_capabilities.shader_model,
-            "Adapter does not support the minimum shader model required to run this example: {:?}",
-            required_downlevel_capabilities.shader_model
-        );
-        assert!(
-            downlevel_capabilities
-                .flags
-                .contains(required_downlevel_capabilities.flags),
-            "Adapter does not support the downlevel capabilities required to run this example: {:?}",
-            required_downlevel_capabilities.flags - downlevel_capabilities.flags
-        );
+        let supported: Vec<_> = library_bridge::get_pipeline_registry()
+            .into_iter()
+            .filter(|pipeline| {
+                let reason = pipeline.unsupported_reason(
+                    adapter_features,
+                    &downlevel_capabilities,
+                    &adapter.limits(),
+                );
+                if let Some(reason) = &reason {
+                    log::warn!("Adapter does not support {}: {reason}", pipeline.name);
+                }
+                reason.is_none()
+            })
+            .collect();
+        let required_features =
+            supported
+                .iter()
+                .fold(wgpu::Features::empty(), |features, pipeline| {
+                    features
+                        | pipeline.required_features
+                        | (pipeline.optional_features & adapter_features)
+                });
 
         // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the surface.
         let needed_limits =
-            library_bridge::pipeline_required_limits().using_resolution(adapter.limits());
+            lib::pipeline_registry::device_limits(&supported).using_resolution(adapter.limits());
 
         let trace_dir = std::env::var("WGPU_TRACE");
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: Some("Device Descriptor"),
-                    required_features: (optional_features & adapter_features) | required_features,
+                    required_features,
                     required_limits: needed_limits,
                     memory_hints: wgpu::MemoryHints::Performance,
                 },
@@ -274,6 +280,15 @@ impl WgpuContext {
             queue,
         }
     }
+
+    /// Why `pipeline` cannot run on the device, `None` if it can.
+    fn unsupported_reason(&self, pipeline: &library_bridge::PipelineInfo) -> Option<String> {
+        pipeline.unsupported_reason(
+            self.device.features(),
+            &self.adapter.get_downlevel_capabilities(),
+            &self.device.limits(),
+        )
+    }
 }
 
 /// Initialize wgpu and run the app.
@@ -282,9 +297,21 @@ async fn run(
     // window: Rc<Window>,
     data: Arc<Mutex<library_bridge::ReloadFlags>>,
 ) {
-    let window_loop = EventLoopWrapper::new(&library_bridge::get_pipeline_name());
+    // Pipelines the user can switch to, refreshed after each reload.
+    let mut registry = library_bridge::get_pipeline_registry();
+    let window_loop = EventLoopWrapper::new(&registry[0].name);
     let mut surface = SurfaceWrapper::new();
     let context = WgpuContext::init_async(&mut surface, window_loop.window.clone()).await;
+    // Start with the first pipeline the device supports, if none the first one shows why.
+    let mut pipeline_name = registry
+        .iter()
+        .find(|pipeline| context.unsupported_reason(pipeline).is_none())
+        .unwrap_or(&registry[0])
+        .name
+        .clone();
+    window_loop.window.set_title(&pipeline_name);
+    // Pipeline picked in the menu, created on the next frame.
+    let mut switch_to: Option<String> = None;
 
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
@@ -310,6 +337,15 @@ async fn run(
 
     let mut egui_renderer: Option<Renderer> = None;
 
+    // Last shader or library reload error, displayed on top of the pipeline.
+    let mut error_overlay = ErrorOverlay::default();
+    // Catch library panics, freezing the pipeline until the library is reloaded.
+    let mut lib_guard = LibGuard::default();
+    // State of the pipeline dropped before the library reloads, restored in the new one.
+    let mut pipeline_state: Option<String> = None;
+    // Layout of the types shared with the library, checked after each reload.
+    let mut layout_fingerprint = library_bridge::layout_fingerprint();
+
     #[allow(clippy::let_unit_value)]
     let _ = (event_loop_function)(
         window_loop.event_loop,
@@ -322,6 +358,10 @@ async fn run(
                 target.set_control_flow(ControlFlow::Poll);
             }
 
+            // The reload waits for the pipeline to be released, do it on any event
+            // rather than on the next frame, which does not come while the window is minimized.
+            release_pipeline(&data, &mut pipeline, &mut pipeline_state, &mut lib_guard);
+
             let mut redraw_requested = false;
 
             if let Event::WindowEvent {
@@ -343,18 +383,19 @@ async fn run(
                     // See: https://github.com/rust-windowing/winit/issues/208
                     // This solves an issue where the app would panic when minimizing on Windows.
 
-                    let Some(pipeline) = &mut pipeline else {
-                        return;
-                    };
-
                     if new_size.width > 0 && new_size.height > 0 {
                         surface.resize(&context, *new_size);
-                        library_bridge::resize_pipeline(
-                            pipeline,
-                            surface.config.as_ref().unwrap(),
-                            &context.device,
-                            &context.queue,
-                        );
+                        // The pipeline may not exist yet if a shader was broken at startup.
+                        if let Some(pipeline) = &mut pipeline {
+                            lib_guard.call("resize_pipeline", || {
+                                library_bridge::resize_pipeline(
+                                    pipeline,
+                                    surface.config.as_ref().unwrap(),
+                                    &context.device,
+                                    &context.queue,
+                                );
+                            });
+                        }
                     }
                 }
             }
@@ -362,25 +403,22 @@ async fn run(
             if SurfaceWrapper::start_condition(&event) {
                 surface.resume(&context, window_loop.window.clone(), true);
 
-                if pipeline.is_none() {
-                    pipeline = Some(
-                        library_bridge::create_pipeline(
-                            surface.surface.as_ref().unwrap(),
-                            &context.device,
-                            &context.adapter,
-                            surface.config.as_ref().unwrap(),
-                        )
-                        .unwrap(),
+                // Not while the library reloads: the pipeline would outlive the library creating it.
+                if pipeline.is_none()
+                    && data.lock().unwrap().lib == library_bridge::LibState::Stable
+                {
+                    // On failure the error is displayed and creation is retried on the next reload.
+                    pipeline = create_pipeline(
+                        &pipeline_name,
+                        surface.surface.as_ref().unwrap(),
+                        surface.config.as_ref().unwrap(),
+                        &context,
+                        &registry,
+                        &input,
+                        &mut pipeline_state,
+                        &mut error_overlay,
+                        &mut lib_guard,
                     );
-
-                    if let Some(camera) =
-                        library_bridge::get_pipeline_camera(pipeline.as_mut().unwrap())
-                    {
-                        let Some(config) = surface.config.as_mut() else {
-                            return;
-                        };
-                        camera.update(&input, [config.width as f32, config.height as f32]);
-                    };
                 }
 
                 if egui_renderer.is_none() {
@@ -403,22 +441,18 @@ async fn run(
                     target.exit();
                 }
 
-                if let Some(pipeline) = &mut pipeline {
-                    library_bridge::process_input(pipeline, &input);
+                if let (Some(pipeline), Some(config)) = (&mut pipeline, surface.config.as_ref()) {
+                    lib_guard.call("process_input", || {
+                        library_bridge::process_input(pipeline, &input);
 
-                    if let Some(camera) = library_bridge::get_pipeline_camera(pipeline) {
-                        let Some(config) = surface.config.as_mut() else {
-                            return;
+                        if let Some(camera) = library_bridge::get_pipeline_camera(pipeline) {
+                            camera.update(&input, [config.width as f32, config.height as f32]);
                         };
-                        camera.update(&input, [config.width as f32, config.height as f32]);
-                    };
+                    });
                 };
             }
 
             if redraw_requested {
-                let Some(pipeline) = &mut pipeline else {
-                    return;
-                };
                 let Some(config) = surface.config.as_mut() else {
                     return;
                 };
@@ -446,37 +480,123 @@ async fn run(
                 // window_loop.window.request_redraw();
 
                 let mut data = data.lock().unwrap();
-                // Reload shaders if needed
-                if !data.shaders.is_empty() {
-                    log::info!("rebuild shaders {:?}", data.shaders);
-                    if let Err(pipeline_error) = library_bridge::update_pipeline_passes(
-                        pipeline,
-                        surface,
-                        &context.device,
-                        &context.adapter,
+                if data.lib == library_bridge::LibState::Reloaded {
+                    lib_guard.resume();
+                    check_layout(&mut layout_fingerprint, &pipeline, &mut lib_guard);
+                    if let Some(reloaded) = lib_guard.call(
+                        "get_pipeline_registry",
+                        library_bridge::get_pipeline_registry,
                     ) {
-                        log::error!("{pipeline_error:?}");
+                        registry = reloaded;
                     }
-                    data.shaders.clear();
                 }
-                if data.lib == lib::reload_flags::LibState::Reloaded {
-                    log::info!("reload lib");
-                    if let Err(pipeline_error) = library_bridge::update_pipeline_passes(
-                        pipeline,
-                        surface,
-                        &context.device,
-                        &context.adapter,
-                    ) {
-                        log::error!("{pipeline_error}");
+                if data.lib == library_bridge::LibState::Stable {
+                    if let Some(name) = switch_to.take() {
+                        // Tear down the running pipeline before creating the chosen one on the same device.
+                        log::info!("switch to pipeline {name}");
+                        free_pipeline(&mut pipeline, &mut lib_guard);
+                        window_loop.window.set_title(&name);
+                        pipeline = create_pipeline(
+                            &name,
+                            surface,
+                            config,
+                            &context,
+                            &registry,
+                            &input,
+                            &mut None,
+                            &mut error_overlay,
+                            &mut lib_guard,
+                        );
+                        pipeline_name = name;
+                    }
+                }
+                if let Some(pipeline) = &mut pipeline {
+                    // Reload shaders if needed
+                    if !data.shaders.is_empty() {
+                        log::info!("rebuild shaders {:?}", data.shaders);
+                        let changes = ShaderChanges::Files(std::mem::take(&mut data.shaders));
+                        update_passes(
+                            pipeline,
+                            surface,
+                            &context,
+                            &changes,
+                            &mut error_overlay,
+                            &mut lib_guard,
+                        );
                     }
+                    if data.lib == lib::reload_flags::LibState::Reloaded {
+                        log::info!("reload lib");
+                        update_passes(
+                            pipeline,
+                            surface,
+                            &context,
+                            &ShaderChanges::All,
+                            &mut error_overlay,
+                            &mut lib_guard,
+                        );
+                        data.lib = library_bridge::LibState::Stable;
+                    }
+                    // Passes the pipeline changed itself, such as override constants edited in the ui.
+                    if data.lib == library_bridge::LibState::Stable {
+                        if let Some(Some(changes)) = lib_guard
+                            .call("take_pipeline_pass_updates", || {
+                                library_bridge::take_pipeline_pass_updates(pipeline)
+                            })
+                        {
+                            update_passes(
+                                pipeline,
+                                surface,
+                                &context,
+                                &changes,
+                                &mut error_overlay,
+                                &mut lib_guard,
+                            );
+                        }
+                    }
+                } else if data.lib != library_bridge::LibState::Reloading
+                    && (!data.shaders.is_empty() || data.lib == library_bridge::LibState::Reloaded)
+                {
+                    // The pipeline was dropped before the library reloaded,
+                    // or failed to build: retry now that something changed.
+                    log::info!("create the pipeline");
+                    data.shaders.clear();
                     data.lib = library_bridge::LibState::Stable;
+                    pipeline = create_pipeline(
+                        &pipeline_name,
+                        surface,
+                        config,
+                        &context,
+                        &registry,
+                        &input,
+                        &mut pipeline_state,
+                        &mut error_overlay,
+                        &mut lib_guard,
+                    );
                 }
                 if data.lib == library_bridge::LibState::Stable {
-                    // Update the pipeline before drawing.
-                    library_bridge::update_pipeline(pipeline, &context.queue);
-
-                    // Render the pipeline first so the ui is on top.
-                    library_bridge::render_frame(pipeline, &view, &context.device, &context.queue);
+                    let rendered = pipeline.as_mut().is_some_and(|pipeline| {
+                        // Update the pipeline before drawing.
+                        lib_guard
+                            .call("update_pipeline", || {
+                                library_bridge::update_pipeline(pipeline, &context.queue);
+                            })
+                            .and_then(|()| {
+                                // Render the pipeline first so the ui is on top.
+                                lib_guard.call("render_frame", || {
+                                    library_bridge::render_frame(
+                                        pipeline,
+                                        &view,
+                                        &context.device,
+                                        &context.queue,
+                                    );
+                                })
+                            })
+                            .is_some()
+                    });
+                    if !rendered {
+                        // Error screen until the pipeline can be created or the library is fixed.
+                        clear_frame(&view, &context);
+                    }
 
                     // Update the ui before drawing.
                     let input = egui_state.take_egui_input(&window_loop.window);
@@ -484,12 +604,20 @@ async fn run(
                     let egui_context = egui_state.egui_ctx();
 
                     egui_context.begin_pass(input);
-                    egui::Window::new(library_bridge::get_pipeline_name()).show(
-                        egui_context,
-                        |ui| {
-                            library_bridge::render_ui(pipeline, ui);
-                        },
-                    );
+                    if !lib_guard.is_frozen() {
+                        switch_to = switch_to.take().or_else(|| {
+                            pipeline_menu(egui_context, &registry, &pipeline_name, &context)
+                        });
+                    }
+                    if let Some(pipeline) = pipeline.as_mut().filter(|_| !lib_guard.is_frozen()) {
+                        egui::Window::new(&pipeline_name).show(egui_context, |ui| {
+                            lib_guard.call("render_ui", || {
+                                library_bridge::render_ui(pipeline, ui);
+                            });
+                        });
+                    }
+                    lib_guard.show(egui_context);
+                    error_overlay.show(egui_context);
 
                     let output = egui_context.end_pass();
                     let paint_jobs =
@@ -563,6 +691,230 @@ async fn run(
     );
 }
 
+/// Release the pipeline if the library is about to reload, then let the reload happen.
+/// The layout of the pipeline may change with the reload:
+/// save its state and drop it while the old library is still loaded.
+/// It is recreated with its state once the library is reloaded.
+fn release_pipeline(
+    data: &Mutex<library_bridge::ReloadFlags>,
+    pipeline: &mut Option<library_bridge::PipelineHandle>,
+    state: &mut Option<String>,
+    lib_guard: &mut LibGuard,
+) {
+    let mut data = data.lock().unwrap();
+    if data.lib != library_bridge::LibState::AboutToReload {
+        return;
+    }
+    if let Some(handle) = pipeline {
+        log::info!("save pipeline state");
+        *state = lib_guard.call("export_pipeline_state", || {
+            library_bridge::export_pipeline_state(handle)
+        });
+    }
+    free_pipeline(pipeline, lib_guard);
+    data.lib = library_bridge::LibState::Reloading;
+}
+
+/// Free the pipeline with the library that created it, which knows its layout.
+/// If the library is frozen, the pipeline is leaked rather than dropped by the runner.
+fn free_pipeline(pipeline: &mut Option<library_bridge::PipelineHandle>, lib_guard: &mut LibGuard) {
+    if let Some(pipeline) = pipeline.take() {
+        lib_guard.call("drop_pipeline", || library_bridge::drop_pipeline(pipeline));
+    }
+}
+
+/// Create the pipeline `name`, restore the `state` saved before the library reloaded and set up its camera.
+/// The state is cleared once restored, and kept if the library panics meanwhile.
+/// On failure, including when the device cannot run the pipeline,
+/// the error is shown on top of an empty screen and `None` is returned
+/// so the runner retries on the next shader or library reload.
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline(
+    name: &str,
+    surface: &wgpu::Surface,
+    config: &wgpu::SurfaceConfiguration,
+    context: &WgpuContext,
+    registry: &[library_bridge::PipelineInfo],
+    input: &WinitInputHelper,
+    state: &mut Option<String>,
+    error_overlay: &mut ErrorOverlay,
+    lib_guard: &mut LibGuard,
+) -> Option<library_bridge::PipelineHandle> {
+    let reason = registry
+        .iter()
+        .find(|pipeline| pipeline.name == name)
+        .and_then(|pipeline| context.unsupported_reason(pipeline));
+    if let Some(reason) = reason {
+        let pipeline_error = PipelineError::UnsupportedPipeline {
+            name: name.to_owned(),
+            reason,
+        };
+        log::error!("{pipeline_error}");
+        error_overlay.set(pipeline_error);
+        return None;
+    }
+
+    let created = lib_guard.call("create_pipeline", || {
+        library_bridge::create_pipeline(name, surface, &context.device, &context.adapter, config)
+    })?;
+    let mut pipeline = match created {
+        Ok(pipeline) => pipeline,
+        Err(pipeline_error) => {
+            log::error!("{pipeline_error}");
+            error_overlay.set(pipeline_error);
+            return None;
+        }
+    };
+
+    let saved_state = state.as_deref();
+    let restored = lib_guard.call("import_pipeline_state", || {
+        if let Some(saved_state) = saved_state {
+            library_bridge::import_pipeline_state(&mut pipeline, saved_state);
+        }
+        if let Some(camera) = library_bridge::get_pipeline_camera(&mut pipeline) {
+            camera.update(input, [config.width as f32, config.height as f32]);
+        }
+    });
+    if restored.is_none() {
+        // The panic froze the library, the state is kept for the pipeline created after the reload.
+        lib_guard.free("drop_pipeline", || library_bridge::drop_pipeline(pipeline));
+        return None;
+    }
+    *state = None;
+    error_overlay.clear();
+    Some(pipeline)
+}
+
+/// Menu switching between the registered pipelines, greying out the ones the device cannot run.
+/// Return the pipeline picked by the user, if any.
+fn pipeline_menu(
+    egui_context: &egui::Context,
+    registry: &[library_bridge::PipelineInfo],
+    current: &str,
+    context: &WgpuContext,
+) -> Option<String> {
+    let mut picked = None;
+    egui::TopBottomPanel::top("pipeline menu").show(egui_context, |ui| {
+        egui::menu::bar(ui, |ui| {
+            ui.menu_button("Pipeline", |ui| {
+                for pipeline in registry {
+                    let reason = context.unsupported_reason(pipeline);
+                    let response = ui.add_enabled(
+                        reason.is_none(),
+                        egui::SelectableLabel::new(pipeline.name == current, &pipeline.name),
+                    );
+                    if response.clicked() {
+                        if pipeline.name != current {
+                            picked = Some(pipeline.name.clone());
+                        }
+                        ui.close_menu();
+                    }
+                    if let Some(reason) = reason {
+                        response.on_disabled_hover_text(reason);
+                    }
+                }
+            });
+        });
+    });
+    picked
+}
+
+/// Compare the layouts of the reloaded library with the ones of the previous version.
+///
+/// The pipeline is released before the reload and recreated from its saved state,
+/// so its layout can change freely. A pipeline still alive would have been built by the old library,
+/// and neither the new one nor the runner can use or drop it if its layout changed.
+/// That, like a change of the types the runner uses itself, compiled with the original library,
+/// requires a restart.
+fn check_layout(
+    fingerprint: &mut library_bridge::LayoutFingerprint,
+    pipeline: &Option<library_bridge::PipelineHandle>,
+    lib_guard: &mut LibGuard,
+) {
+    let Some(reloaded) = lib_guard.call("layout_fingerprint", library_bridge::layout_fingerprint)
+    else {
+        return;
+    };
+
+    if reloaded.boundary != fingerprint.boundary {
+        lib_guard.require_restart(
+            "The layout of the types shared with the runner changed with the reload".to_owned(),
+        );
+        return;
+    }
+
+    if reloaded.pipeline != fingerprint.pipeline {
+        if pipeline.is_some() {
+            lib_guard.require_restart(
+                "The layout of the pipeline changed while it was still alive".to_owned(),
+            );
+            return;
+        }
+        fingerprint.pipeline = reloaded.pipeline;
+    }
+}
+
+/// Clear the frame, drawn instead of the pipeline when it could not be created.
+fn clear_frame(view: &wgpu::TextureView, context: &WgpuContext) {
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("clear render pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color {
+                    r: 0.1,
+                    g: 0.02,
+                    b: 0.02,
+                    a: 1.0,
+                }),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    });
+    context.queue.submit(Some(encoder.finish()));
+}
+
+/// Rebuild the passes affected by `changes`, showing the error on top of the pipeline if it fails.
+/// On success only the errors of the rebuilt shaders are hidden.
+fn update_passes(
+    pipeline: &mut library_bridge::PipelineHandle,
+    surface: &wgpu::Surface,
+    context: &WgpuContext,
+    changes: &ShaderChanges,
+    error_overlay: &mut ErrorOverlay,
+    lib_guard: &mut LibGuard,
+) {
+    let updated = lib_guard.call("update_pipeline_passes", || {
+        // Forget the shaders built outside of this update.
+        library_bridge::take_built_shader_files();
+        library_bridge::update_pipeline_passes(
+            pipeline,
+            surface,
+            &context.device,
+            &context.adapter,
+            changes,
+        )
+        .map(|()| library_bridge::take_built_shader_files())
+    });
+    match updated {
+        // Frozen or panicked, shown by the guard.
+        None => {}
+        // Other shaders may still be broken, only clear the errors of the rebuilt ones.
+        Some(Ok(files)) => error_overlay.clear_files(&files),
+        Some(Err(pipeline_error)) => {
+            log::error!("{pipeline_error}");
+            error_overlay.set(pipeline_error);
+        }
+    }
+}
+
 /// Create the window depending on the platform.
 pub fn start_app(data: Arc<Mutex<lib::reload_flags::ReloadFlags>>) {
     cfg_if::cfg_if! {
@@ -572,6 +924,7 @@ pub fn start_app(data: Arc<Mutex<lib::reload_flags::ReloadFlags>>) {
             wasm_bindgen_futures::spawn_local(async move { run(data).await })
         } else {
             env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+            crate::lib_guard::install_panic_hook();
             pollster::block_on(run(data));
         }
     }
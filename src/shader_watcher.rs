@@ -0,0 +1,305 @@
+//! Turn the events of the shader folder watcher into batches of changed shaders.
+//!
+//! A single save often produces several events, for the file, its swap file and its folder.
+//! Events are filtered with globs, de-duplicated, and only sent once the folder is quiet.
+//! The coalescing does not depend on notify so it can be tested without a filesystem.
+//!
+//! Several folders can be watched: the main shader folder and the prefixed
+//! [`lib::SHADER_ROOTS`], whose shaders are named like their imports, such as `shared:math.wgsl`.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use lib::composed_shader::SHADER_EXTENSIONS;
+use lib::shader_builder::LIBRARY_SHADERS_FOLDER;
+
+/// Shader folder watched for changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchedRoot {
+    /// Prefix of the shader names, empty for the main folder.
+    pub prefix: &'static str,
+    pub folder: PathBuf,
+}
+
+impl WatchedRoot {
+    /// Name of the shader at `path`, or `None` if it is not in the folder.
+    pub fn shader_name(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.folder).ok()?;
+        // Imports always use forward slashes.
+        Some(format!(
+            "{}{}",
+            self.prefix,
+            relative.to_string_lossy().replace('\\', "/")
+        ))
+    }
+}
+
+/// Folder of the main shaders, in order of priority: the `--shaders <DIR>` argument,
+/// the `SHADER_ROOT` environment variable, and the folder embedded by `LibraryShaders`,
+/// which does not depend on the working directory.
+pub fn shader_root(mut args: impl Iterator<Item = String>, var: Option<OsString>) -> PathBuf {
+    while let Some(arg) = args.next() {
+        if arg == "--shaders" {
+            if let Some(folder) = args.next() {
+                return folder.into();
+            }
+        } else if let Some(folder) = arg.strip_prefix("--shaders=") {
+            return folder.into();
+        }
+    }
+    var.map_or_else(|| LIBRARY_SHADERS_FOLDER.into(), PathBuf::from)
+}
+
+/// The main shader folder and the folders of the [`lib::SHADER_ROOTS`].
+pub fn watched_roots(main: PathBuf) -> Vec<WatchedRoot> {
+    std::iter::once(WatchedRoot {
+        prefix: "",
+        folder: main,
+    })
+    .chain(lib::SHADER_ROOTS.iter().map(|root| WatchedRoot {
+        prefix: root.prefix,
+        folder: root.folder.into(),
+    }))
+    .collect()
+}
+
+/// Settings of the shader watcher.
+#[derive(Clone, Debug)]
+pub struct WatchSettings {
+    /// Changes are sent once no event was received for this long.
+    pub quiet_period: Duration,
+    /// Paths relative to their shader folder to watch, see [`glob_match`].
+    pub include: Vec<String>,
+    /// Paths ignored even if they are included, such as editor swap files.
+    pub exclude: Vec<String>,
+}
+
+impl Default for WatchSettings {
+    /// Watch the shader extensions, ignoring hidden and backup files.
+    fn default() -> Self {
+        Self {
+            quiet_period: Duration::from_millis(100),
+            include: SHADER_EXTENSIONS
+                .iter()
+                .map(|extension| format!("**/*.{extension}"))
+                .collect(),
+            exclude: vec![
+                // Hidden files, such as vim `.draw.wgsl.swp` or emacs `.#draw.wgsl`.
+                "**/.*".to_owned(),
+                // Files in hidden folders, such as `.git`.
+                "**/.*/**".to_owned(),
+                "**/*~".to_owned(),
+            ],
+        }
+    }
+}
+
+impl WatchSettings {
+    /// Whether a change to `path`, relative to its shader folder, should reload the shaders.
+    pub fn is_watched(&self, path: &str) -> bool {
+        self.include.iter().any(|glob| glob_match(glob, path))
+            && !self.exclude.iter().any(|glob| glob_match(glob, path))
+    }
+}
+
+/// Coalesce watcher events into batches of changed shaders.
+pub struct EventCoalescer {
+    settings: WatchSettings,
+    /// Changed shaders not sent yet, in the order they were first changed.
+    pending: Vec<String>,
+    /// Time of the last event that changed a watched shader.
+    last_event: Option<Instant>,
+}
+
+impl EventCoalescer {
+    pub fn new(settings: WatchSettings) -> Self {
+        Self {
+            settings,
+            pending: vec![],
+            last_event: None,
+        }
+    }
+
+    /// Record the shaders changed by an event received at `now`, named like by [`WatchedRoot`].
+    /// Paths that are not watched are ignored and do not delay the pending changes.
+    pub fn push<'a>(&mut self, paths: impl IntoIterator<Item = &'a str>, now: Instant) {
+        for path in paths {
+            // Globs apply to the path in the folder, without its prefix.
+            let relative = path.split_once(':').map_or(path, |(_, relative)| relative);
+            if !self.settings.is_watched(relative) {
+                continue;
+            }
+            if !self.pending.iter().any(|pending| pending == path) {
+                self.pending.push(path.to_owned());
+            }
+            self.last_event = Some(now);
+        }
+    }
+
+    /// Time left at `now` before the pending changes are sent, `None` if there are none.
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.last_event.map(|last_event| {
+            self.settings
+                .quiet_period
+                .saturating_sub(now.saturating_duration_since(last_event))
+        })
+    }
+
+    /// Take the changed shaders if no event was received during the quiet period.
+    pub fn flush(&mut self, now: Instant) -> Option<Vec<String>> {
+        if self.timeout(now)? > Duration::ZERO {
+            return None;
+        }
+        self.last_event = None;
+        Some(std::mem::take(&mut self.pending))
+    }
+}
+
+/// Match a `/` separated path against a glob:
+/// - `*` matches any characters in a path component, `?` matches a single one,
+/// - `**` matches any number of components, including none.
+pub fn glob_match(glob: &str, path: &str) -> bool {
+    let glob: Vec<_> = glob.split('/').collect();
+    let path: Vec<_> = path.split('/').collect();
+    match_components(&glob, &path)
+}
+
+fn match_components(glob: &[&str], path: &[&str]) -> bool {
+    match glob.split_first() {
+        None => path.is_empty(),
+        Some((&"**", glob)) => (0..=path.len()).any(|skip| match_components(glob, &path[skip..])),
+        Some((pattern, glob)) => path.split_first().is_some_and(|(component, path)| {
+            let pattern: Vec<_> = pattern.chars().collect();
+            let component: Vec<_> = component.chars().collect();
+            match_component(&pattern, &component) && match_components(glob, path)
+        }),
+    }
+}
+
+fn match_component(pattern: &[char], component: &[char]) -> bool {
+    match pattern.split_first() {
+        None => component.is_empty(),
+        Some(('*', pattern)) => {
+            (0..=component.len()).any(|skip| match_component(pattern, &component[skip..]))
+        }
+        Some((expected, pattern)) => component.split_first().is_some_and(|(found, component)| {
+            (*expected == '?' || expected == found) && match_component(pattern, component)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("**/*.wgsl", "draw.wgsl"));
+        assert!(glob_match("**/*.wgsl", "demos/polygon/draw.wgsl"));
+        assert!(!glob_match("**/*.wgsl", "demos/polygon/draw.wgsl.swp"));
+        assert!(!glob_match("**/*.wgsl", "demos/polygon"));
+        assert!(glob_match("demos/*/draw.wgsl", "demos/boids/draw.wgsl"));
+        assert!(!glob_match(
+            "demos/*/draw.wgsl",
+            "demos/boids/nested/draw.wgsl"
+        ));
+        assert!(glob_match("**/?raw.wgsl", "demos/boids/draw.wgsl"));
+        assert!(glob_match("**/.*/**", ".git/objects/ab"));
+        assert!(!glob_match("**/.*/**", "demos/draw.wgsl"));
+    }
+
+    #[test]
+    fn test_watch_settings() {
+        let settings = WatchSettings::default();
+        assert!(settings.is_watched("demos/polygon/draw.wgsl"));
+        assert!(settings.is_watched("test_glsl/common.glsl"));
+        assert!(settings.is_watched("test_glsl/particles.comp"));
+        assert!(!settings.is_watched("demos/polygon"));
+        assert!(!settings.is_watched("demos/polygon/.draw.wgsl.swp"));
+        assert!(!settings.is_watched("demos/polygon/.#draw.wgsl"));
+        assert!(!settings.is_watched("demos/polygon/draw.wgsl~"));
+        assert!(!settings.is_watched(".git/draw.wgsl"));
+        assert!(!settings.is_watched("README.md"));
+    }
+
+    #[test]
+    fn test_coalesce_events() {
+        let mut coalescer = EventCoalescer::new(WatchSettings {
+            quiet_period: Duration::from_millis(100),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(coalescer.timeout(start), None);
+        assert_eq!(coalescer.flush(start), None);
+
+        // Noise does not start the quiet period.
+        coalescer.push(["demos/polygon", "demos/polygon/.draw.wgsl.swp"], at(0));
+        assert_eq!(coalescer.timeout(at(0)), None);
+
+        // A save producing several events, then another file saved before the folder is quiet.
+        coalescer.push(["demos/polygon/draw.wgsl", "demos/polygon"], at(0));
+        coalescer.push(["demos/polygon/draw.wgsl"], at(10));
+        coalescer.push(["demos/boids/draw.wgsl"], at(60));
+        assert_eq!(coalescer.timeout(at(100)), Some(Duration::from_millis(60)));
+        assert_eq!(coalescer.flush(at(100)), None);
+
+        assert_eq!(
+            coalescer.flush(at(160)).unwrap(),
+            ["demos/polygon/draw.wgsl", "demos/boids/draw.wgsl"]
+        );
+        assert_eq!(coalescer.timeout(at(160)), None);
+        assert_eq!(coalescer.flush(at(500)), None);
+
+        // Prefixed shaders are filtered without their prefix.
+        coalescer.push(["shared:.math.wgsl.swp", "shared:math.wgsl"], at(500));
+        assert_eq!(coalescer.flush(at(600)).unwrap(), ["shared:math.wgsl"]);
+    }
+
+    #[test]
+    fn test_shader_root() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let var = Some(OsString::from("var"));
+
+        assert_eq!(
+            shader_root(args(&["--shaders", "flag"]).into_iter(), var.clone()),
+            PathBuf::from("flag")
+        );
+        assert_eq!(
+            shader_root(args(&["--shaders=flag"]).into_iter(), var.clone()),
+            PathBuf::from("flag")
+        );
+        assert_eq!(
+            shader_root(args(&["--other"]).into_iter(), var),
+            PathBuf::from("var")
+        );
+        assert_eq!(
+            shader_root(args(&[]).into_iter(), None),
+            PathBuf::from(LIBRARY_SHADERS_FOLDER)
+        );
+    }
+
+    #[test]
+    fn test_watched_roots() {
+        let roots = watched_roots("shaders".into());
+        assert_eq!(roots[0].prefix, "");
+        assert!(roots.iter().any(|root| root.prefix == "shared:"));
+
+        let shared = WatchedRoot {
+            prefix: "shared:",
+            folder: "/project/shared_shaders".into(),
+        };
+        assert_eq!(
+            shared
+                .shader_name(Path::new("/project/shared_shaders/noise/simplex.wgsl"))
+                .unwrap(),
+            "shared:noise/simplex.wgsl"
+        );
+        assert_eq!(
+            shared.shader_name(Path::new("/project/shaders/draw.wgsl")),
+            None
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! `#[derive(WgslStruct)]`, generating the wgsl declaration of a rust struct shared with shaders.
+//! See `lib::wgsl_struct` for the generated traits and how shaders import the declaration.
+
+use proc_macro::TokenStream;
+use proc_macro_crate::{crate_name, FoundCrate};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields};
+
+/// Implement `WgslStruct`, `WgslType` and `StructLayout` for a `#[repr(C)]` struct with named fields,
+/// and register it so shaders can import it.
+#[proc_macro_derive(WgslStruct)]
+pub fn derive_wgsl_struct(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Path of the `lib` crate from the crate deriving the struct.
+fn lib_path() -> syn::Result<proc_macro2::TokenStream> {
+    match crate_name("lib") {
+        Ok(FoundCrate::Itself) => Ok(quote!(crate)),
+        Ok(FoundCrate::Name(name)) => {
+            let name = format_ident!("{name}");
+            Ok(quote!(::#name))
+        }
+        Err(error) => Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            format!("WgslStruct requires the `lib` crate: {error}"),
+        )),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let lib = lib_path()?;
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "WgslStruct cannot be derived for generic structs",
+        ));
+    }
+
+    // Offsets are only stable with a C layout.
+    let mut repr_c = false;
+    for attribute in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+    {
+        attribute.parse_nested_meta(|meta| {
+            repr_c |= meta.path.is_ident("C");
+            // Skip the arguments of `align(N)` or `packed(N)`.
+            if meta.input.peek(syn::token::Paren) {
+                let _arguments;
+                syn::parenthesized!(_arguments in meta.input);
+            }
+            Ok(())
+        })?;
+    }
+    if !repr_c {
+        return Err(syn::Error::new_spanned(
+            name,
+            "WgslStruct requires #[repr(C)]",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "WgslStruct requires named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "WgslStruct can only be derived for structs",
+            ))
+        }
+    };
+
+    let idents: Vec<_> = fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let names: Vec<_> = idents.iter().map(|ident| ident.to_string()).collect();
+    let wgsl_name = name.to_string();
+
+    Ok(quote! {
+        #lib::wgsl_struct::inventory::submit! {
+            #lib::wgsl_struct::RustStruct::of::<#name>()
+        }
+
+        impl #lib::wgsl_struct::WgslStruct for #name {
+            const WGSL_NAME: &'static str = #wgsl_name;
+
+            fn wgsl_fields() -> Vec<#lib::wgsl_struct::WgslField> {
+                vec![
+                    #(
+                        #lib::wgsl_struct::WgslField::new::<#types>(
+                            #names,
+                            ::std::mem::offset_of!(Self, #idents),
+                        ),
+                    )*
+                ]
+            }
+        }
+
+        impl #lib::wgsl_struct::WgslType for #name {
+            fn wgsl_type() -> String {
+                #wgsl_name.to_owned()
+            }
+
+            fn wgsl_align() -> usize {
+                #lib::wgsl_struct::struct_align::<Self>()
+            }
+
+            fn wgsl_size() -> usize {
+                #lib::wgsl_struct::struct_size::<Self>()
+            }
+
+            fn wgsl_imports(imports: &mut Vec<String>) {
+                imports.push(#lib::wgsl_struct::import_name::<Self>());
+            }
+        }
+
+        impl #lib::struct_layout::StructLayout for #name {
+            fn scalars(
+                name: &str,
+                offset: usize,
+                padding: bool,
+                scalars: &mut Vec<#lib::struct_layout::LayoutScalar>,
+            ) {
+                #(
+                    #lib::struct_layout::field_scalars(
+                        |value: &Self| &value.#idents,
+                        name,
+                        #names,
+                        offset + ::std::mem::offset_of!(Self, #idents),
+                        padding,
+                        scalars,
+                    );
+                )*
+            }
+        }
+    })
+}